@@ -6,4 +6,12 @@ pub struct Args {
     /// Generate a config file when none is available
     #[arg(short = 'c', long)]
     pub generate_config: bool,
+
+    /// Subscribe to all servers and print sessions as they start or end
+    #[arg(short = 'f', long)]
+    pub follow: bool,
+
+    /// Show every session in detail, with the user's real name and status when servers provide them
+    #[arg(short = 'd', long)]
+    pub details: bool,
 }
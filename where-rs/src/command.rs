@@ -0,0 +1,105 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use whrd::error::{WhereError, WhereResult};
+use whrd::{Session, SessionCollection};
+use crate::finger::parse_login_time;
+
+/// Runs a command that prints who(1) output, such as `ssh host who -u --ips`, once.  Returns
+/// nothing if it didn't finish in time, in which case it is killed.
+pub fn attempt_fetch(command: &str, timeout: Duration, label: &str) -> WhereResult<Option<SessionCollection>> {
+    let mut child = shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Reading in the background lets the timeout apply even if the output never ends
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut output = vec![];
+        let _ = sender.send(stdout.read_to_end(&mut output).map(|_| output));
+    });
+
+    let output = match receiver.recv_timeout(timeout) {
+        Ok(output) => output?,
+        Err(_) => {
+            // It may have exited in the meantime, which is fine
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+    };
+
+    let status = child.wait()?;
+
+    if !status.success() {
+        return Err(WhereError::CommandFailed(command.to_string(), status));
+    }
+
+    let sessions = String::from_utf8_lossy(&output)
+        .lines()
+        .filter_map(|line| parse_line(line, label))
+        .collect();
+
+    Ok(Some(SessionCollection::from_vec(sessions)))
+}
+
+/// Prepares a command line to run through the system's shell.
+#[cfg(unix)]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+pub fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Parses a line of who(1) output, with or without -u, -T or --ips.  Lines without a login
+/// time, such as headers, are skipped and fields that aren't there, like the process ID
+/// without -u, are left empty.
+fn parse_line(line: &str, label: &str) -> Option<Session> {
+    // The remote host, or X display, is in parentheses at the end
+    let (fields, remote) = match line.trim_end().strip_suffix(')').and_then(|line| line.split_once(" (")) {
+        Some((fields, remote)) => (fields, Some(remote.to_string()).filter(|remote| !remote.is_empty())),
+        None => (line, None)
+    };
+
+    let mut fields = fields.split_whitespace().peekable();
+    let user = fields.next()?.to_string();
+
+    // who -T shows whether the terminal can be written to after the user
+    fields.next_if(|field| matches!(*field, "+" | "-" | "?"));
+
+    let tty = fields.next()?.to_string();
+    let rest: Vec<&str> = fields.collect();
+
+    // GNU who shows ISO dates, BSD who shows the month's name
+    let (login_time, rest) = [2, 3].into_iter()
+        .filter(|length| rest.len() >= *length)
+        .find_map(|length| parse_login_time(&rest[..length].join(" ")).map(|time| (time, &rest[length..])))?;
+
+    // With -u, the idle time comes first and the process ID last, if there is one
+    let pid = rest.last().and_then(|pid| pid.parse().ok()).unwrap_or(0);
+
+    Some(Session {
+        host: Some(label.to_string()),
+        pid,
+        login_time,
+        user,
+        tty,
+        remote,
+        active: true,
+        real_name: None,
+        status: None,
+        origin: None,
+    })
+}
@@ -5,6 +5,7 @@ use crate::args::Args;
 
 const TIMEOUT: u64 = 2000;
 const MAX_SEND_RETRIES: usize = 3;
+const SUBSCRIPTION_LEASE: u32 = 60;
 const CONFIG_FILENAME: &str = "where.toml";
 
 #[derive(Deserialize, Debug, Default)]
@@ -21,12 +22,39 @@ pub struct GlobalConfig {
     pub max_retries: usize,
     pub include_inactive: bool,
     pub port: u16,
-    pub source: String
+    pub source: String,
+    pub lease: u32
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Whrd,
+    Rwho,
+    Finger,
+    Command,
+    Stdio,
+    Unix
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// UDP, then TCP if the response is too large or UDP gets no response
+    #[default]
+    Auto,
+    Udp,
+    Tcp
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Server {
     pub endpoint: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub transport: Transport,
     pub label: Option<String>,
     pub timeout: Option<u64>,
     pub max_retries: Option<usize>,
@@ -40,7 +68,8 @@ impl Default for GlobalConfig {
             max_retries: MAX_SEND_RETRIES,
             include_inactive: true,
             port: 15,
-            source: "Local".to_string()
+            source: "Local".to_string(),
+            lease: SUBSCRIPTION_LEASE
         }
     }
 }
@@ -102,7 +131,7 @@ impl Config {
         ]
     }
 
-    pub fn build(args: Args) -> Self {
+    pub fn build(args: &Args) -> Self {
         let config: Option<Config> = Self::get_config_locations()
             .iter()
             .flat_map(|path| fs::read_to_string(path).ok())
@@ -0,0 +1,179 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use chrono::{Datelike, Days, Local, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+use whrd::error::{WhereError, WhereResult};
+use whrd::{Session, SessionCollection};
+
+pub const FINGER_PORT: u16 = 79;
+// Nothing a finger server lists for a single host should come close to this
+const MAX_RESPONSE_LENGTH: u64 = 1024 * 1024;
+
+/// The columns of a finger listing that can be turned into session fields, found by their
+/// title in the header line.
+#[derive(Default)]
+struct Columns {
+    name: Option<usize>,
+    tty: Option<usize>,
+    login_time: Option<usize>,
+    remote: Option<usize>,
+    starts: Vec<usize>
+}
+
+/// Asks a finger server for everyone logged in, once.  Returns nothing if it timed out.
+pub fn attempt_fetch(address: &SocketAddr, timeout: Duration, label: &str) -> WhereResult<Option<SessionCollection>> {
+    let res = TcpStream::connect_timeout(address, timeout).and_then(|mut stream| {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        stream.write_all(b"\r\n")?;
+
+        let mut response = vec![];
+        stream.take(MAX_RESPONSE_LENGTH).read_to_end(&mut response)?;
+        Ok(response)
+    });
+
+    match res {
+        Ok(response) => Ok(Some(parse_listing(&String::from_utf8_lossy(&response), label))),
+        Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(WhereError::from(e))
+    }
+}
+
+/// Parses a who-style listing, as most finger servers send.  Fields that can't be found are
+/// left empty.
+fn parse_listing(response: &str, label: &str) -> SessionCollection {
+    let mut lines = response.lines();
+
+    // Anything before the header, such as "No one logged on", is not a session
+    let Some(columns) = lines.by_ref().find_map(Columns::from_header) else {
+        return SessionCollection::get_empty();
+    };
+
+    let sessions = lines
+        .take_while(|line| !line.trim().is_empty())
+        .filter_map(|line| columns.parse_row(line, label))
+        .collect();
+
+    SessionCollection::from_vec(sessions)
+}
+
+impl Columns {
+    fn from_header(line: &str) -> Option<Self> {
+        let lower = line.to_lowercase();
+
+        if !lower.trim_start().starts_with("login") || !lower.contains("tty") {
+            return None;
+        }
+
+        let find = |titles: &[&str]| titles.iter().find_map(|title| find_title(&lower, title));
+
+        let mut columns = Self {
+            name: find(&["name"]),
+            tty: find(&["tty", "line"]),
+            login_time: find(&["login time", "when", "since"]),
+            remote: find(&["where", "from", "office"]),
+            starts: vec![]
+        };
+
+        columns.starts = [Some(0), columns.name, columns.tty, find(&["idle"]), columns.login_time, columns.remote, find(&["office phone"])]
+            .into_iter()
+            .flatten()
+            .collect();
+        columns.starts.sort_unstable();
+        columns.starts.dedup();
+
+        Some(columns)
+    }
+
+    /// Cuts the column starting at `start` out of a row, up to the start of the next one.
+    fn field<'a>(&self, row: &'a str, start: Option<usize>) -> Option<&'a str> {
+        let start = start?;
+        let end = self.starts.iter().find(|s| **s > start).copied().unwrap_or(usize::MAX);
+
+        let chars: Vec<(usize, char)> = row.char_indices().collect();
+        let byte = |index: usize| chars.get(index).map_or(row.len(), |(byte, _)| *byte);
+
+        Some(row[byte(start)..byte(end)].trim()).filter(|field| !field.is_empty())
+    }
+
+    fn parse_row(&self, row: &str, label: &str) -> Option<Session> {
+        let user = row.split_whitespace().next()?.to_string();
+
+        // Some servers mark TTYs that can't be written to with a star
+        let tty = self.field(row, self.tty)
+            .and_then(|tty| tty.split_whitespace().next())
+            .map(|tty| tty.trim_start_matches('*').to_string())
+            .unwrap_or_default();
+
+        let login_time = self.field(row, self.login_time)
+            .and_then(|field| parse_login_time(field.split('(').next().unwrap().trim()))
+            .unwrap_or_default();
+
+        // Remote hosts are often in parentheses, at the end of the row
+        let remote = row.trim_end().strip_suffix(')')
+            .and_then(|row| row.rsplit_once('('))
+            .map(|(_, remote)| remote)
+            .or_else(|| self.field(row, self.remote))
+            .map(str::to_string);
+
+        Some(Session {
+            host: Some(label.to_string()),
+            pid: 0,
+            login_time,
+            user,
+            tty,
+            remote,
+            active: true,
+            real_name: self.field(row, self.name).map(str::to_string),
+            status: None,
+            origin: None,
+        })
+    }
+}
+
+/// Finds where a column title starts, in characters, only matching whole words.
+fn find_title(header: &str, title: &str) -> Option<usize> {
+    header.match_indices(title)
+        .find(|(index, _)| {
+            let before = header[..*index].chars().next_back();
+            let after = header[index + title.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+        .map(|(index, _)| header[..index].chars().count())
+}
+
+/// Parses the login times finger servers commonly show, in their local time.  Those without a
+/// year or a date are taken to be the most recent ones that fit.
+pub fn parse_login_time(field: &str) -> Option<i64> {
+    let now = Local::now();
+
+    let datetime = NaiveDateTime::parse_from_str(field, "%Y-%m-%d %H:%M").ok()
+        .or_else(|| {
+            // Without a year, the login is assumed to be from the last twelve months
+            let this_year = NaiveDateTime::parse_from_str(&format!("{} {field}", now.year()), "%Y %b %d %H:%M").ok()?;
+
+            if this_year > now.naive_local() {
+                this_year.with_year(now.year() - 1)
+            } else {
+                Some(this_year)
+            }
+        })
+        .or_else(|| {
+            // BSD servers only show the day of the week for logins from the last seven days
+            let (weekday, time) = field.split_once(' ')?;
+            let weekday: Weekday = weekday.parse().ok()?;
+            let time = NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()?;
+
+            let days_ago = (now.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+            let datetime = (now.date_naive() - Days::new(days_ago.into())).and_time(time);
+
+            if datetime > now.naive_local() {
+                Some(datetime - Days::new(7))
+            } else {
+                Some(datetime)
+            }
+        })?;
+
+    Local.from_local_datetime(&datetime).earliest().map(|datetime| datetime.timestamp())
+}
@@ -0,0 +1,12 @@
+//! The client side of where-rs, which whered also uses to query other servers when relaying
+//! their sessions.
+
+pub mod args;
+pub mod command;
+pub mod config;
+pub mod finger;
+pub mod local;
+pub mod rwho;
+pub mod servers;
+pub mod stdio;
+pub mod tcp;
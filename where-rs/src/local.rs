@@ -0,0 +1,25 @@
+use std::path::Path;
+use std::time::Duration;
+use whrd::error::WhereResult;
+use whrd::request::Request;
+use whrd::SessionCollection;
+
+/// Sends one request to whered through its local socket.  Returns nothing if it timed out.
+#[cfg(unix)]
+pub fn attempt_fetch(path: &Path, request: Request, timeout: Duration, label: &str) -> WhereResult<Option<SessionCollection>> {
+    use std::os::unix::net::UnixStream;
+    use crate::tcp;
+
+    let mut connection = UnixStream::connect(path)?;
+    connection.set_read_timeout(Some(timeout))?;
+    connection.set_write_timeout(Some(timeout))?;
+
+    tcp::exchange(&mut connection, request, label)
+}
+
+#[cfg(not(unix))]
+pub fn attempt_fetch(_path: &Path, _request: Request, _timeout: Duration, _label: &str) -> WhereResult<Option<SessionCollection>> {
+    use std::io::{self, ErrorKind};
+
+    Err(io::Error::new(ErrorKind::Unsupported, "Unix sockets are not supported on this system").into())
+}
@@ -1,12 +1,23 @@
-mod config;
-mod servers;
 mod ui;
-mod args;
 
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
 use clap::Parser;
-use args::Args;
-use whrd::error::WhereResult;
-use config::{Config, Server};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use whrd::error::{WhereError, WhereResult};
+use whrd::request::Request;
+use whrd::subscription::{parse_subscription_ack, SessionEvent};
+use whrd::{MAX_PAYLOAD_LENGTH, WHERED_NOTIFY_MAGIC, WHERED_SUBSCRIBE_MAGIC};
+use where_rs::args::Args;
+use where_rs::config::{Config, GlobalConfig, Protocol, Server};
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
 
 fn main() {
     if let Err(e) = start_client() {
@@ -17,10 +28,15 @@ fn main() {
 
 fn start_client() -> WhereResult<()> {
     let args = Args::parse();
-    let config = Config::build(args);
+    let config = Config::build(&args);
     let global_config = config.global;
 
     let servers: Vec<Server> = config.server;
+
+    if args.follow {
+        return follow_servers(servers, global_config);
+    }
+
     let mut sessions = vec![];
 
     for server in servers {
@@ -42,6 +58,174 @@ fn start_client() -> WhereResult<()> {
         sessions.extend(res.into_vec());
     }
 
-    ui::print_summary(sessions, global_config);
+    if args.details {
+        ui::print_details(sessions, global_config);
+    } else {
+        ui::print_summary(sessions, global_config);
+    }
+
     Ok(())
 }
+
+fn follow_servers(servers: Vec<Server>, global_config: GlobalConfig) -> WhereResult<()> {
+    // One socket per address family, as servers can be reached over IPv4 or IPv6
+    let mut sockets = vec![];
+    let mut labels = HashMap::new();
+    let mut renewals = vec![];
+
+    for server in servers {
+        if server.protocol != Protocol::Whrd {
+            eprintln!("where: {} can't be followed, only whered servers can", server.endpoint);
+
+            if !server.failsafe.unwrap_or(false) {
+                std::process::exit(1);
+            }
+
+            continue;
+        }
+
+        let res = server.get_address(&global_config)
+            .and_then(|address| Ok((address, server.subscribe(socket_for(&mut sockets, &address)?, &address, &global_config)?)));
+
+        match res {
+            Ok((address, (lease, cookie))) if lease > 0 => {
+                labels.insert(address, server.get_label());
+                renewals.push((address, Instant::now() + renewal_interval(lease), cookie));
+            }
+            Ok(_) => {
+                eprintln!("where: {} refused the subscription", server.endpoint);
+
+                if !server.failsafe.unwrap_or(false) {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("where: {e}");
+
+                if !server.failsafe.unwrap_or(false) {
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    if renewals.is_empty() {
+        return Ok(());
+    }
+
+    // Servers are told to stop sending changes on the way out, rather than until the lease ends
+    let stop = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, stop.clone())?;
+    }
+
+    let received = receive_all(&sockets)?;
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            for (address, _, _) in &renewals {
+                socket_for(&mut sockets, address)?.send_to(&Request::Unsubscribe.to_bytes(), *address)?;
+            }
+
+            return Ok(());
+        }
+
+        let now = Instant::now();
+
+        for (address, renew_at, cookie) in renewals.iter_mut().filter(|(_, renew_at, _)| *renew_at <= now) {
+            socket_for(&mut sockets, address)?.send_to(&Request::Subscribe(global_config.lease, Some(*cookie)).to_bytes(), *address)?;
+            // Retry soon if no acknowledgement arrives, it will push this back
+            *renew_at = now + Duration::from_millis(global_config.timeout);
+        }
+
+        let next_renewal = renewals.iter().map(|(_, renew_at, _)| *renew_at).min().unwrap();
+        let timeout = next_renewal.saturating_duration_since(now).clamp(Duration::from_millis(1), STOP_CHECK_INTERVAL);
+
+        let (buf, length, src) = match received.recv_timeout(timeout) {
+            Ok(Ok(datagram)) => datagram,
+            Ok(Err(e)) => return Err(WhereError::from(e)),
+            // Also gives the signal handlers a chance, they are checked at the top of the loop
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+
+        let Some(label) = labels.get(&src) else {
+            continue;
+        };
+
+        if buf.starts_with(&WHERED_NOTIFY_MAGIC) {
+            match SessionEvent::from_udp_payload(*buf, label) {
+                Ok(events) => events.into_iter().for_each(|event| ui::print_event(event, &global_config)),
+                Err(e) => eprintln!("where: {e}"),
+            }
+        } else if buf.starts_with(&WHERED_SUBSCRIBE_MAGIC) {
+            let (lease, new_cookie) = parse_subscription_ack(&buf[..length])?;
+            let Some((_, renew_at, cookie)) = renewals.iter_mut().find(|(address, _, _)| *address == src) else {
+                continue;
+            };
+
+            if lease > 0 {
+                *renew_at = Instant::now() + renewal_interval(lease);
+                *cookie = new_cookie;
+            } else if new_cookie != 0 && new_cookie != *cookie {
+                // The server restarted or the cookie is too old, so renew right away with a new one
+                *renew_at = Instant::now();
+                *cookie = new_cookie;
+            } else {
+                eprintln!("where: {label} refused to renew the subscription");
+            }
+        }
+    }
+}
+
+/// Finds the socket for servers of the same address family as `address`, binding it the
+/// first time.
+fn socket_for<'a>(sockets: &'a mut Vec<UdpSocket>, address: &SocketAddr) -> io::Result<&'a UdpSocket> {
+    let index = match sockets.iter().position(|socket| socket.local_addr().is_ok_and(|local| local.is_ipv4() == address.is_ipv4())) {
+        Some(index) => index,
+        None => {
+            let unspecified = if address.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+            sockets.push(UdpSocket::bind(SocketAddr::new(unspecified, 0))?);
+            sockets.len() - 1
+        }
+    };
+
+    Ok(&sockets[index])
+}
+
+type Datagram = (Box<[u8; MAX_PAYLOAD_LENGTH]>, usize, SocketAddr);
+
+/// Receives on every socket from its own thread, so that the datagrams can be waited for
+/// together.
+fn receive_all(sockets: &[UdpSocket]) -> io::Result<mpsc::Receiver<io::Result<Datagram>>> {
+    let (sender, receiver) = mpsc::channel();
+
+    for socket in sockets {
+        let socket = socket.try_clone()?;
+        let sender = sender.clone();
+        // Subscribing left a timeout behind
+        socket.set_read_timeout(None)?;
+
+        thread::spawn(move || loop {
+            let mut buf = Box::new([0; MAX_PAYLOAD_LENGTH]);
+
+            let res = match socket.recv_from(&mut buf[..]) {
+                Ok((length, src)) => Ok((buf, length, src)),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+
+            if sender.send(res).is_err() {
+                break;
+            }
+        });
+    }
+
+    Ok(receiver)
+}
+
+fn renewal_interval(lease: u32) -> Duration {
+    // Renew halfway through the lease so that one lost packet does not end the subscription
+    Duration::from_millis(lease as u64 * 500)
+}
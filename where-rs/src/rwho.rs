@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use whrd::error::WhereResult;
+use whrd::rwho::Whod;
+use whrd::SessionCollection;
+
+// Like ruptime, hosts that haven't been heard from in 11 minutes are considered down
+const HOST_DOWN_AFTER: i64 = 11 * 60;
+const SPOOL_FILE_PREFIX: &str = "whod.";
+
+/// Reads the sessions of every host that is up from an rwhod spool directory.
+pub fn read_spool(directory: &Path) -> WhereResult<SessionCollection> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64);
+    let mut sessions = vec![];
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+
+        if !entry.file_name().to_string_lossy().starts_with(SPOOL_FILE_PREFIX) {
+            continue;
+        }
+
+        // rwhod may be writing the file right now, so broken ones are skipped rather than fatal
+        let Some(whod) = fs::read(entry.path()).ok().and_then(|buf| Whod::from_spool_bytes(&buf).ok()) else {
+            continue;
+        };
+
+        if now - whod.recv_time > HOST_DOWN_AFTER {
+            continue;
+        }
+
+        sessions.extend(whod.into_sessions().into_vec());
+    }
+
+    Ok(SessionCollection::from_vec(sessions))
+}
@@ -1,27 +1,37 @@
-use std::io::ErrorKind;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
 use std::time::Duration;
-use whrd::error::{WhereError, WhereResult};
-use whrd::{MAX_PAYLOAD_LENGTH, SessionCollection, WHERED_MAGIC};
-use crate::config::{GlobalConfig, Server};
+use whrd::error::{EncodeDecodeError, WhereError, WhereResult};
+use whrd::request::Request;
+use whrd::subscription::parse_subscription_ack;
+use whrd::{MAX_PAYLOAD_LENGTH, SessionCollection, WHERED_SUBSCRIBE_MAGIC};
+use crate::config::{GlobalConfig, Protocol, Server, Transport};
+use crate::{command, finger, local, rwho, stdio, tcp};
 
 impl Server {
-    fn get_address(&self, config: &GlobalConfig) -> WhereResult<SocketAddr> {
-        let res: SocketAddr = match self.endpoint.to_socket_addrs() {
-            Ok(mut addr) => {
-                addr.find(|i| i.is_ipv4()).unwrap()
-            },
-            Err(_) => {
-                let mut endpoint = self.endpoint.clone();
-                let port = config.port.to_string();
+    pub fn get_address(&self, config: &GlobalConfig) -> WhereResult<SocketAddr> {
+        let default_port = match self.protocol {
+            Protocol::Finger => finger::FINGER_PORT,
+            _ => config.port
+        };
 
-                endpoint.push(':');
-                endpoint.push_str(&port);
-                endpoint.to_socket_addrs()?.find(|i| i.is_ipv4()).unwrap()
-            }
+        // Bare IPv6 addresses can't have a port appended to them
+        if let Ok(ip) = self.endpoint.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, default_port));
+        }
+
+        let addresses: Vec<SocketAddr> = match self.endpoint.to_socket_addrs() {
+            Ok(addresses) => addresses.collect(),
+            Err(_) => format!("{}:{default_port}", self.endpoint).to_socket_addrs()?.collect()
         };
 
-        Ok(res)
+        // Hosts with both kinds of addresses are still reached over IPv4
+        addresses.iter().find(|address| address.is_ipv4())
+            .or_else(|| addresses.first())
+            .copied()
+            .ok_or_else(|| WhereError::from(io::Error::new(ErrorKind::NotFound, format!("{} has no address", self.endpoint))))
     }
 
     fn create_socket(&self, address: &SocketAddr, timeout: Duration) -> WhereResult<UdpSocket> {
@@ -35,8 +45,8 @@ impl Server {
         Ok(socket)
     }
 
-    fn attempt_fetch(socket: &UdpSocket, address: &SocketAddr, mut buf: [u8; MAX_PAYLOAD_LENGTH], label: &str) -> WhereResult<Option<SessionCollection>> {
-        socket.send_to(&WHERED_MAGIC, address)?;
+    fn attempt_fetch(socket: &UdpSocket, address: &SocketAddr, request: Request, mut buf: [u8; MAX_PAYLOAD_LENGTH], label: &str) -> WhereResult<Option<SessionCollection>> {
+        socket.send_to(&request.to_bytes(), address)?;
 
         match socket.recv_from(&mut buf) {
             Ok(_) => {
@@ -48,21 +58,169 @@ impl Server {
         }
     }
 
+    pub fn get_label(&self) -> String {
+        self.label.clone().unwrap_or(self.endpoint.to_owned())
+    }
+
+    /// Fetches the sessions of this server.  Extended queries are used so that the sessions a
+    /// server relays, and real names and statuses, come along, falling back to a plain query
+    /// for servers that don't answer them.
     pub fn process(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
-        let label = self.label.clone().unwrap_or(self.endpoint.to_owned());
+        match self.protocol {
+            Protocol::Whrd => self.fetch(config),
+            // The endpoint is a spool directory, with one file per host
+            Protocol::Rwho => rwho::read_spool(Path::new(&self.endpoint)),
+            Protocol::Finger => self.fetch_finger(config),
+            Protocol::Command => self.fetch_command(config),
+            Protocol::Stdio => self.fetch_stdio(config),
+            Protocol::Unix => self.fetch_local(config)
+        }
+    }
+
+    /// Queries a whered server through its local socket, given as the endpoint.
+    fn fetch_local(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
+        let label = self.get_label();
+        let retries = self.max_retries.unwrap_or(config.max_retries);
+        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+        for _ in 0..retries {
+            if let Some(c) = local::attempt_fetch(Path::new(&self.endpoint), Request::ExtendedQuery, timeout, &label)? {
+                return Ok(c);
+            }
+        }
+
+        Err(WhereError::TimedOut(label, self.endpoint.to_string(), retries, timeout))
+    }
+
+    /// Runs the endpoint as a command that speaks WHRD on its standard input and output.
+    fn fetch_stdio(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
+        let label = self.get_label();
+        let retries = self.max_retries.unwrap_or(config.max_retries);
+        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+        for _ in 0..retries {
+            if let Some(c) = stdio::attempt_fetch(&self.endpoint, Request::ExtendedQuery, timeout, &label)? {
+                return Ok(c);
+            }
+        }
+
+        Err(WhereError::TimedOut(label, self.endpoint.to_string(), retries, timeout))
+    }
+
+    /// Runs the endpoint as a command and reads its who(1) output.
+    fn fetch_command(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
+        let label = self.get_label();
+        let retries = self.max_retries.unwrap_or(config.max_retries);
+        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+
+        for _ in 0..retries {
+            if let Some(c) = command::attempt_fetch(&self.endpoint, timeout, &label)? {
+                return Ok(c);
+            }
+        }
+
+        Err(WhereError::TimedOut(label, self.endpoint.to_string(), retries, timeout))
+    }
+
+    fn fetch_finger(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
+        let label = self.get_label();
+        let retries = self.max_retries.unwrap_or(config.max_retries);
+        let address = self.get_address(config)?;
+        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+
+        for _ in 0..retries {
+            if let Some(c) = finger::attempt_fetch(&address, timeout, &label)? {
+                return Ok(c);
+            }
+        }
+
+        Err(WhereError::TimedOut(self.endpoint.to_string(), address.to_string(), retries, timeout))
+    }
+
+    fn fetch(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
+        let label = self.get_label();
         let retries = self.max_retries.unwrap_or(config.max_retries);
         let address = self.get_address(config)?;
         let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+
+        if self.transport == Transport::Tcp {
+            return self.fetch_tcp(&address, Request::ExtendedQuery, retries, timeout, &label);
+        }
+
+        let auto = self.transport == Transport::Auto;
         let socket = self.create_socket(&address, timeout)?;
         let buf = [0; MAX_PAYLOAD_LENGTH];
+        let mut failure = None;
 
-        for _ in 0..retries {
-            match Self::attempt_fetch(&socket, &address, buf, &label)? {
-                Some(c) => return Ok(c),
-                None => continue
+        // Older servers ignore extended queries altogether, but so does the network once in a
+        // while, so a plain query is only sent once every attempt went unanswered
+        let requests = iter::repeat_n(Request::ExtendedQuery, retries)
+            .chain(iter::once(Request::Query));
+
+        for request in requests {
+            match Self::attempt_fetch(&socket, &address, request, buf, &label) {
+                Ok(Some(c)) => return Ok(c),
+                Ok(None) => (),
+                // Like DNS, the server says the response only fits over TCP
+                Err(WhereError::EncodeDecodeError(EncodeDecodeError::TruncatedPayload)) if auto => {
+                    return self.fetch_tcp(&address, Request::ExtendedQuery, retries, timeout, &label);
+                },
+                // Such as an unreachable port, when only TCP gets through
+                Err(e @ WhereError::IOError(_)) if auto => {
+                    failure = Some(e);
+                    break;
+                },
+                Err(e) => return Err(e)
             };
         }
 
+        // Responses may also have been fragmented and dropped on the way, which TCP avoids
+        if auto {
+            if let Ok(Some(c)) = tcp::attempt_fetch(&address, Request::ExtendedQuery, timeout, &label) {
+                return Ok(c);
+            }
+        }
+
+        Err(failure.unwrap_or_else(|| WhereError::TimedOut(self.endpoint.to_string(), address.to_string(), retries, timeout)))
+    }
+
+    fn fetch_tcp(&self, address: &SocketAddr, request: Request, retries: usize, timeout: Duration, label: &str) -> WhereResult<SessionCollection> {
+        for _ in 0..retries {
+            if let Some(c) = tcp::attempt_fetch(address, request, timeout, label)? {
+                return Ok(c);
+            }
+        }
+
+        Err(WhereError::TimedOut(self.endpoint.to_string(), address.to_string(), retries, timeout))
+    }
+
+    /// Asks the server to send session changes to `socket` and returns the lease it granted,
+    /// along with the cookie to renew it with.
+    pub fn subscribe(&self, socket: &UdpSocket, address: &SocketAddr, config: &GlobalConfig) -> WhereResult<(u32, u64)> {
+        let retries = self.max_retries.unwrap_or(config.max_retries);
+        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
+        let mut buf = [0; MAX_PAYLOAD_LENGTH];
+        let mut cookie = 0;
+        socket.set_read_timeout(Some(timeout))?;
+
+        let mut attempts = 0;
+
+        while attempts < retries {
+            socket.send_to(&Request::Subscribe(config.lease, Some(cookie)).to_bytes(), address)?;
+
+            match socket.recv_from(&mut buf) {
+                Ok((length, src)) if src == *address && buf.starts_with(&WHERED_SUBSCRIBE_MAGIC) => {
+                    match parse_subscription_ack(&buf[..length])? {
+                        // The server wants its cookie back before granting anything, which
+                        // doesn't count as an attempt
+                        (0, new_cookie) if new_cookie != 0 && new_cookie != cookie => cookie = new_cookie,
+                        ack => return Ok(ack)
+                    }
+                },
+                Ok(_) => attempts += 1,
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => attempts += 1,
+                Err(e) => return Err(WhereError::from(e)),
+            }
+        }
+
         Err(WhereError::TimedOut(self.endpoint.to_string(), address.to_string(), retries, timeout))
     }
 }
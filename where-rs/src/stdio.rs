@@ -0,0 +1,53 @@
+use std::io::{self, ErrorKind};
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use whrd::error::{WhereError, WhereResult};
+use whrd::request::Request;
+use whrd::{stream, SessionCollection, MAX_STREAM_PAYLOAD_LENGTH};
+use crate::command;
+
+/// Runs a command that speaks WHRD on its standard input and output, such as
+/// `ssh host whered --stdio`, and sends it one request.  Returns nothing if it didn't answer
+/// in time, in which case it is killed.
+pub fn attempt_fetch(command: &str, request: Request, timeout: Duration, label: &str) -> WhereResult<Option<SessionCollection>> {
+    let mut child = command::shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let _ = sender.send(stream::read_frame(&mut stdout, MAX_STREAM_PAYLOAD_LENGTH));
+    });
+
+    // Closing standard input right away lets whered exit once it has answered
+    let written = stream::write_frame(&mut stdin, &request.to_bytes());
+    drop(stdin);
+
+    let Ok(frame) = receiver.recv_timeout(timeout) else {
+        // It may have exited in the meantime, which is fine
+        let _ = child.kill();
+        child.wait()?;
+        return Ok(None);
+    };
+
+    // A command that failed, such as ssh not reaching the host, says more than the pipe
+    let status = child.wait()?;
+
+    if !status.success() {
+        return Err(WhereError::CommandFailed(command.to_string(), status));
+    }
+
+    written?;
+
+    let Some(frame) = frame? else {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "No response on standard output").into());
+    };
+
+    Ok(Some(SessionCollection::from_payload(&frame, label)?))
+}
@@ -0,0 +1,41 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use whrd::error::{EncodeDecodeError, WhereError, WhereResult};
+use whrd::request::Request;
+use whrd::{stream, SessionCollection, MAX_STREAM_PAYLOAD_LENGTH};
+
+/// Sends one request to a whered server over TCP.  Returns nothing if it timed out.
+pub fn attempt_fetch(address: &SocketAddr, request: Request, timeout: Duration, label: &str) -> WhereResult<Option<SessionCollection>> {
+    let res = TcpStream::connect_timeout(address, timeout).and_then(|connection| {
+        connection.set_read_timeout(Some(timeout))?;
+        connection.set_write_timeout(Some(timeout))?;
+        Ok(connection)
+    });
+
+    match res {
+        Ok(mut connection) => exchange(&mut connection, request, label),
+        Err(e) if is_timeout(&e) => Ok(None),
+        Err(e) => Err(WhereError::from(e))
+    }
+}
+
+/// Sends one request over a connected stream and reads the response.  Returns nothing if it
+/// timed out.
+pub fn exchange(connection: &mut (impl Read + Write), request: Request, label: &str) -> WhereResult<Option<SessionCollection>> {
+    let frame = stream::write_frame(connection, &request.to_bytes())
+        .and_then(|_| stream::read_frame(connection, MAX_STREAM_PAYLOAD_LENGTH));
+
+    let frame = match frame {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Connection closed without a response").into()),
+        Err(EncodeDecodeError::IOErrorWhileTranscoding(e)) if is_timeout(&e) => return Ok(None),
+        Err(e) => return Err(WhereError::from(e))
+    };
+
+    Ok(Some(SessionCollection::from_payload(&frame, label)?))
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock
+}
@@ -1,6 +1,7 @@
-use chrono::DateTime;
+use chrono::{DateTime, Local};
 use whrd::Session;
-use crate::config::GlobalConfig;
+use whrd::subscription::{EventKind, SessionEvent};
+use where_rs::config::GlobalConfig;
 
 pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
     fn max_key_with_min<T, F>(sessions: &[Session], get_key: F, floor: T) -> T
@@ -68,8 +69,9 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
         let host = session.host.unwrap_or_else(|| ' '.to_string());
         let remote = session.remote.unwrap_or_else(|| config.source.clone());
 
-        let datetime = DateTime::from_timestamp(session.login_time, 0).unwrap();
-        let time = datetime.format("%Y-%m-%d %H:%M:%S");
+        // Backends that can't tell the process ID or login time leave them at zero
+        let pid = Some(session.pid).filter(|pid| *pid != 0).map(|pid| pid.to_string()).unwrap_or_default();
+        let time = format_time(session.login_time);
 
         if config.include_inactive {
             println!(" {:<pad_0$}  {:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  {}",
@@ -78,7 +80,7 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
                      remote,
                      session.user,
                      session.tty,
-                     session.pid,
+                     pid,
                      time,
                      pad_0 = ACTIVE_PADDING,
                      pad_1 = host_padding,
@@ -92,7 +94,7 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
                      remote,
                      session.user,
                      session.tty,
-                     session.pid,
+                     pid,
                      time,
                      pad_1 = host_padding,
                      pad_2 = remote_padding,
@@ -102,3 +104,77 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
         }
     }
 }
+
+pub fn print_details(mut sessions: Vec<Session>, config: GlobalConfig) {
+    sessions.sort_unstable_by_key(|s| s.login_time);
+    sessions.sort_by_key(|s| !s.active); // We want active first
+
+    let mut first = true;
+
+    for session in sessions {
+        if !config.include_inactive && !session.active {
+            continue;
+        }
+
+        if !first {
+            println!();
+        }
+
+        first = false;
+
+        let host = session.host.unwrap_or_else(|| ' '.to_string());
+        let remote = session.remote.unwrap_or_else(|| config.source.clone());
+
+        println!("{} on {host} ({}) from {remote}", session.user, session.tty);
+
+        if session.login_time != 0 {
+            println!("  Since:  {}{}", format_time(session.login_time), if session.active { "" } else { " (logged out)" });
+        }
+
+        if session.pid != 0 {
+            println!("  PID:    {}", session.pid);
+        }
+
+        // Users write these themselves, so they mustn't get to send escape sequences to the
+        // terminal
+        if let Some(real_name) = session.real_name {
+            println!("  Name:   {}", sanitize(&real_name));
+        }
+
+        if let Some(status) = session.status {
+            println!("  Status: {}", sanitize(&status));
+        }
+
+        if let Some(origin) = session.origin {
+            println!("  Origin: {}", sanitize(&origin));
+        }
+    }
+}
+
+/// Replaces control characters, so that a field prints as text and nothing else.
+fn sanitize(field: &str) -> String {
+    field.chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect()
+}
+
+fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .filter(|_| timestamp != 0)
+        .map(|datetime| datetime.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+pub fn print_event(event: SessionEvent, config: &GlobalConfig) {
+    let session = event.session;
+    let host = session.host.unwrap_or_else(|| ' '.to_string());
+    let remote = session.remote.unwrap_or_else(|| config.source.clone());
+    let time = Local::now().format("%Y-%m-%d %H:%M:%S");
+
+    let action = match event.kind {
+        EventKind::Started => "logged in",
+        EventKind::Ended => "logged out",
+    };
+
+    println!("{time}  {host}: {} {action} on {} from {remote} (PID {})", session.user, session.tty, session.pid);
+}
@@ -0,0 +1,79 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::net::UdpSocket;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use socket2::{SockRef, Type};
+use whrd::error::WhereResult;
+
+// The first file descriptor passed by systemd, after standard input, output and error
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the sockets passed by systemd through the LISTEN_FDS protocol, if any.
+pub fn listen_fds() -> WhereResult<Vec<UdpSocket>> {
+    let for_us = env::var("LISTEN_PID").ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS").ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if !for_us {
+        return Ok(vec![]);
+    }
+
+    let mut sockets = vec![];
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        set_cloexec(fd)?;
+        sockets.push(datagram_socket(fd)?);
+    }
+
+    Ok(sockets)
+}
+
+/// Takes over the socket passed on standard input by inetd in "wait" mode.
+pub fn inetd_socket() -> WhereResult<UdpSocket> {
+    // Standard input, output and error are all the socket, so move it out of the way and
+    // point them to /dev/null instead to not write messages into it
+    let fd = unsafe { libc::fcntl(0, libc::F_DUPFD_CLOEXEC, LISTEN_FDS_START) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let socket = datagram_socket(fd)?;
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+
+    for target in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), target) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(socket)
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn datagram_socket(fd: RawFd) -> WhereResult<UdpSocket> {
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    if SockRef::from(&socket).r#type()? != Type::DGRAM {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("File descriptor {fd} is not a datagram socket")).into());
+    }
+
+    Ok(socket)
+}
@@ -1,9 +1,73 @@
-use clap::Parser;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(name = "whered", version, about)]
 pub struct Args {
-    /// Specify a custom listen address from the default 0.0.0.0:15
+    /// Specify a custom listen address from the default 0.0.0.0:15, can be repeated
     #[arg(short = 'l', long)]
-    pub listen_addr: Option<String>,
+    pub listen_addr: Vec<String>,
+
+    /// Specify a custom configuration file from the default /etc/whered.toml
+    #[arg(short = 'c', long)]
+    pub config: Option<PathBuf>,
+
+    /// Serve the socket passed on standard input by inetd, in "wait" mode
+    #[arg(long)]
+    pub inetd: bool,
+
+    /// Answer requests framed on standard input on standard output, as run through SSH
+    #[arg(long, conflicts_with = "inetd")]
+    pub stdio: bool,
+
+    /// Switch to this user once the sockets are bound
+    #[arg(short = 'u', long)]
+    pub user: Option<String>,
+
+    /// Switch to this group once the sockets are bound
+    #[arg(short = 'g', long)]
+    pub group: Option<String>,
+
+    /// Only log messages at this level or above (off, error, warn, info, debug or trace)
+    #[arg(long)]
+    pub log_level: Option<LevelFilter>,
+
+    /// Allow whered to keep running as root
+    #[arg(long)]
+    pub allow_root: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Send a command to a running whered through its control socket
+    Ctl {
+        /// Specify a custom control socket from the one in the configuration file
+        #[arg(short = 's', long)]
+        socket: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: ControlCommand,
+    },
+    /// Print the sessions in utmp as JSON, to serve them again later as a fixture
+    Record,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Show request and session counters
+    Stats,
+    /// List the subscribed clients and when their subscription expires
+    Subscribers,
+    /// Show the cached list of sessions
+    Cache,
+    /// Read the configuration file again
+    Reload,
+    /// Refuse new subscriptions and shut down once the current ones have expired
+    Drain,
+    /// Finish pending requests and shut down
+    Stop,
 }
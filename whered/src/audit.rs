@@ -0,0 +1,126 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use whrd::request::Request;
+use crate::config::AuditConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Served,
+    Refused,
+    Rejected,
+    RateLimited,
+    Failed
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Served => "served",
+            Self::Refused => "refused",
+            Self::Rejected => "rejected",
+            Self::RateLimited => "rate_limited",
+            Self::Failed => "failed"
+        }
+    }
+}
+
+pub fn request_name(request: Request) -> &'static str {
+    match request {
+        Request::Query => "query",
+        Request::ExtendedQuery => "extended_query",
+        Request::Subscribe(..) => "subscribe",
+        Request::Unsubscribe => "unsubscribe"
+    }
+}
+
+pub struct AuditRecord {
+    pub source: SocketAddr,
+    pub request: Option<&'static str>,
+    pub filters: Vec<String>,
+    pub sessions: Option<usize>,
+    pub outcome: Outcome
+}
+
+/// Writes one JSON object per line for every request received, rotating the file once it
+/// grows past the configured size.
+pub struct AuditLog {
+    config: AuditConfig,
+    file: File,
+    size: u64
+}
+
+impl AuditLog {
+    /// Opens the audit log, or returns nothing if it is disabled.
+    pub fn new(config: AuditConfig) -> io::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let file = Self::open_file(&config.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Some(Self {
+            config,
+            file,
+            size
+        }))
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    pub fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+        let line = json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "source": record.source.to_string(),
+            // Clients can't authenticate yet
+            "key_id": null,
+            "request": record.request,
+            "filters": record.filters,
+            "sessions": record.sessions,
+            "outcome": record.outcome.as_str()
+        }).to_string() + "\n";
+
+        if self.config.max_size > 0 && self.size > 0 && self.size + line.len() as u64 > self.config.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Renames the log to "<path>.1", shifting older files up to "<path>.<max_files>" and
+    /// deleting the oldest one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |index: usize| {
+            let mut path = self.config.path.clone().into_os_string();
+            path.push(format!(".{index}"));
+            PathBuf::from(path)
+        };
+
+        if self.config.max_files == 0 {
+            fs::remove_file(&self.config.path)?;
+        } else {
+            for index in (1..self.config.max_files).rev() {
+                match fs::rename(rotated(index), rotated(index + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+
+            fs::rename(&self.config.path, rotated(1))?;
+        }
+
+        self.file = Self::open_file(&self.config.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
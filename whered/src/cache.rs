@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use log::warn;
+use whrd::{Session, SessionCollection, WHERED_TRUNCATED_MAGIC};
+use whrd::error::{EncodeDecodeError, EncodeDecodeResult};
+use crate::config::{CacheConfig, DetailsConfig, OptOutConfig, PrivacyConfig};
+use crate::fixture;
+use crate::metrics::{Health, Metrics, HEALTH, METRICS};
+use crate::privacy;
+use crate::registry::Registry;
+use crate::relay::Relay;
+
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+
+// Privacy rule set, whether the format is extended and whether it is for a stream
+type PayloadKey = (Option<usize>, bool, bool);
+
+/// The sessions read at one point in time, which stay the same once read so that any
+/// number of threads can answer from them at once.
+pub struct Snapshot {
+    pub sessions: SessionCollection,
+    pub generation: u64,
+    privacy: Arc<[PrivacyConfig]>,
+    fetched_at: Instant,
+    // Encoded sessions along with how many there are, for every privacy rule set, format and
+    // transport used so far
+    payloads: Mutex<HashMap<PayloadKey, (Vec<u8>, usize)>>
+}
+
+pub struct SessionCache {
+    config: CacheConfig,
+    privacy: Arc<[PrivacyConfig]>,
+    opt_out: OptOutConfig,
+    details: DetailsConfig,
+    snapshot: Option<Arc<Snapshot>>,
+    generation: u64,
+    registry: Registry,
+    relay: Option<Arc<Relay>>,
+    refreshing: bool,
+    // Bumped whenever the configuration changes, so that sessions read with the previous one
+    // are thrown away
+    epoch: u64,
+    #[cfg(target_os = "linux")]
+    watcher: Option<Inotify>
+}
+
+/// Everything needed to read the sessions again, taken from the cache so that the reading
+/// happens without holding it.
+struct Refresh {
+    config: CacheConfig,
+    opt_out: OptOutConfig,
+    details: DetailsConfig,
+    registered: Vec<Session>,
+    relay: Option<Arc<Relay>>,
+    epoch: u64
+}
+
+/// The cache shared by every thread.  Only one of them reads the sessions again at a time,
+/// while the others keep answering from the previous snapshot.
+pub struct SharedCache {
+    cache: Mutex<SessionCache>,
+    refreshed: Condvar
+}
+
+impl SessionCache {
+    pub fn new(config: CacheConfig, privacy: Vec<PrivacyConfig>, opt_out: OptOutConfig, details: DetailsConfig) -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            watcher: Self::create_watcher(&config),
+            config,
+            privacy: privacy.into(),
+            opt_out,
+            details,
+            snapshot: None,
+            generation: 0,
+            registry: Registry::default(),
+            relay: None,
+            refreshing: false,
+            epoch: 0
+        }
+    }
+
+    /// Applies a new configuration, which also throws away the current snapshot.
+    pub fn reconfigure(&mut self, config: CacheConfig, privacy: Vec<PrivacyConfig>, opt_out: OptOutConfig, details: DetailsConfig) {
+        #[cfg(target_os = "linux")]
+        {
+            self.watcher = Self::create_watcher(&config);
+        }
+
+        self.config = config;
+        self.privacy = privacy.into();
+        self.opt_out = opt_out;
+        self.details = details;
+        self.snapshot = None;
+        self.refreshing = false;
+        self.epoch += 1;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn create_watcher(config: &CacheConfig) -> Option<Inotify> {
+        if !config.enabled {
+            return None;
+        }
+
+        Self::watch_utmp(config).map_err(|e| {
+            warn!("Unable to watch {} for changes, using a {} ms TTL instead: {e}", config.source_path().display(), config.ttl);
+        }).ok()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn watch_utmp(config: &CacheConfig) -> std::io::Result<Inotify> {
+        // Watch the directory rather than the file itself, so that the watch survives utmp
+        // being replaced instead of written to
+        let directory = config.source_path().parent()
+            .filter(|path| !path.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."));
+
+        let inotify = Inotify::init()?;
+        inotify.watches().add(directory, WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::DELETE)?;
+
+        Ok(inotify)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn utmp_changed(&mut self) -> Option<bool> {
+        let watcher = self.watcher.as_mut()?;
+        let file_name = self.config.source_path().file_name();
+        let mut buffer = [0; 4096];
+        let mut changed = false;
+
+        loop {
+            match watcher.read_events(&mut buffer) {
+                Ok(events) => {
+                    let mut events = events.peekable();
+
+                    if events.peek().is_none() {
+                        break;
+                    }
+
+                    changed |= events.any(|event| event.name == file_name);
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Stopped watching {} for changes, using a {} ms TTL instead: {e}", self.config.source_path().display(), self.config.ttl);
+                    self.watcher = None;
+                    return None;
+                }
+            }
+        }
+
+        Some(changed)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn utmp_changed(&mut self) -> Option<bool> {
+        None
+    }
+
+    fn is_stale(&mut self) -> bool {
+        if !self.config.enabled || self.registry.is_outdated() {
+            return true;
+        }
+
+        let Some(fetched_at) = self.snapshot.as_ref().map(|s| s.fetched_at) else {
+            return true;
+        };
+
+        if self.relay.as_ref().is_some_and(|relay| relay.changed_since(fetched_at)) {
+            return true;
+        }
+
+        match self.utmp_changed() {
+            Some(changed) => changed,
+            None => fetched_at.elapsed() >= Duration::from_millis(self.config.ttl)
+        }
+    }
+
+    /// Takes what reading the sessions again needs, if they have to be.  Only one refresh is
+    /// handed out at a time while caching, as the others can use the current snapshot.
+    fn start_refresh(&mut self) -> Option<Refresh> {
+        if self.refreshing || !self.is_stale() {
+            return None;
+        }
+
+        self.refreshing = self.config.enabled;
+
+        Some(Refresh {
+            config: self.config.clone(),
+            opt_out: self.opt_out.clone(),
+            details: self.details.clone(),
+            registered: self.registry.sessions(),
+            relay: self.relay.clone(),
+            epoch: self.epoch
+        })
+    }
+
+    /// Keeps the sessions read by a refresh, unless the configuration changed in the meantime.
+    fn finish_refresh(&mut self, epoch: u64, sessions: SessionCollection, fetched_at: Instant) -> Option<Arc<Snapshot>> {
+        if epoch != self.epoch {
+            return None;
+        }
+
+        self.refreshing = false;
+        self.generation += 1;
+        let snapshot = Arc::new(Snapshot {
+            sessions,
+            generation: self.generation,
+            privacy: self.privacy.clone(),
+            fetched_at,
+            payloads: Mutex::new(HashMap::new())
+        });
+
+        if self.config.enabled {
+            self.snapshot = Some(snapshot.clone());
+        }
+
+        Some(snapshot)
+    }
+
+    /// The sessions applications registered, which are listed along with the ones in utmp.
+    pub fn registry(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    /// Lists the sessions of downstream servers along with the local ones from now on, or
+    /// stops if there is no relay anymore.  Returns the previous relay.
+    pub fn set_relay(&mut self, relay: Option<Arc<Relay>>) -> Option<Arc<Relay>> {
+        self.snapshot = None;
+        self.refreshing = false;
+        self.epoch += 1;
+        std::mem::replace(&mut self.relay, relay)
+    }
+
+    pub fn relay(&self) -> Option<&Arc<Relay>> {
+        self.relay.as_ref()
+    }
+}
+
+impl SharedCache {
+    pub fn new(cache: SessionCache) -> Self {
+        Self {
+            cache: Mutex::new(cache),
+            refreshed: Condvar::new()
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, SessionCache> {
+        self.cache.lock().unwrap()
+    }
+
+    /// Returns the current sessions, reading utmp again only if it changed since they were
+    /// last read.  Threads asking while another one reads them get the previous ones, or
+    /// wait if there are none yet.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        let mut cache = self.lock();
+
+        loop {
+            let Some(refresh) = cache.start_refresh() else {
+                match &cache.snapshot {
+                    Some(snapshot) => return snapshot.clone(),
+                    None => {
+                        cache = self.refreshed.wait(cache).unwrap();
+                        continue;
+                    }
+                }
+            };
+            drop(cache);
+
+            let epoch = refresh.epoch;
+            let fetched_at = Instant::now();
+            let sessions = refresh.read();
+
+            cache = self.lock();
+            let snapshot = cache.finish_refresh(epoch, sessions, fetched_at);
+            self.refreshed.notify_all();
+
+            if let Some(snapshot) = snapshot {
+                return snapshot;
+            }
+        }
+    }
+}
+
+impl Refresh {
+    fn read(self) -> SessionCollection {
+        let started_at = Instant::now();
+        let mut sessions = read_sessions(&self.config).into_vec();
+
+        Metrics::increment(&METRICS.utmp_reads);
+        Metrics::set(&METRICS.utmp_read_micros, started_at.elapsed().as_micros() as u64);
+        Metrics::set(&METRICS.sessions, sessions.len() as u64);
+
+        sessions.extend(self.registered);
+        let sessions = SessionCollection::from_vec(sessions);
+
+        // Opting out has to come last, so that anonymized sessions lose their details too
+        let sessions = self.opt_out.apply(self.details.apply(sessions));
+
+        // Relayed sessions already went through the opt-outs of the servers they come from
+        match &self.relay {
+            Some(relay) => {
+                let mut sessions = sessions.into_vec();
+                sessions.extend(relay.sessions());
+                SessionCollection::from_vec(sessions)
+            },
+            None => sessions
+        }
+    }
+}
+
+/// Reads the sessions in utmp, or in the fixture if one is configured.
+fn read_sessions(config: &CacheConfig) -> SessionCollection {
+    if let Some(path) = &config.fixture {
+        let sessions = fixture::load(path);
+        Health::set(&HEALTH.read_failed, sessions.is_err());
+
+        return sessions.unwrap_or_else(|e| {
+            warn!("Unable to read sessions from {}: {e}", path.display());
+            SessionCollection::get_empty()
+        });
+    }
+
+    SessionCollection::fetch()
+}
+
+impl Snapshot {
+    /// How long ago the sessions were read from utmp.
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed()
+    }
+
+    /// Returns the sessions encoded for a client, after applying the privacy rules for it,
+    /// along with how many sessions were kept.  They are encoded for a datagram, or with
+    /// `stream` for TCP or a pipe, which leaves room for more sessions.
+    pub fn payload(&self, client: IpAddr, extended: bool, stream: bool) -> EncodeDecodeResult<(Vec<u8>, usize)> {
+        let rule = privacy::rule_for(&self.privacy, client);
+        let key = (rule, extended, stream);
+
+        if let Some(payload) = self.payloads.lock().unwrap().get(&key) {
+            return Ok(payload.clone());
+        }
+
+        // Encoding happens without holding the lock, so that threads answering other clients
+        // don't wait.  Two threads may both encode the same payload, which is harmless.
+        let sessions = self.sessions_for(client);
+
+        // Only extended entries say which host a session is on, and other clients can't
+        // read them, so they only get the local sessions
+        let sessions = if extended {
+            sessions
+        } else {
+            SessionCollection::from_vec(sessions.into_vec().into_iter().filter(|session| session.host.is_none()).collect())
+        };
+        let count = sessions.len();
+
+        let payload = if stream {
+            sessions.to_stream_payload(extended)
+        } else if extended {
+            sessions.to_extended_udp_payload()
+        } else {
+            sessions.to_udp_payload()
+        };
+
+        let payload = match payload {
+            // The client is told to ask again over TCP, like DNS does
+            Err(EncodeDecodeError::InvalidPayloadLength(_)) if !stream => {
+                Metrics::increment(&METRICS.responses_truncated);
+                Ok(WHERED_TRUNCATED_MAGIC.to_vec())
+            }
+            payload => payload
+        };
+        Health::set(&HEALTH.encode_failed, payload.is_err());
+        let payload = payload.inspect_err(|_| Metrics::increment(&METRICS.encode_errors))?;
+        self.payloads.lock().unwrap().insert(key, (payload.clone(), count));
+
+        Ok((payload, count))
+    }
+
+    /// Returns the sessions after applying the privacy rules for a client.
+    pub fn sessions_for(&self, client: IpAddr) -> SessionCollection {
+        match privacy::rule_for(&self.privacy, client) {
+            Some(index) => self.privacy[index].apply(&self.sessions),
+            None => self.sessions.clone()
+        }
+    }
+
+    /// The privacy rules the sessions are given out under.
+    pub fn privacy(&self) -> &[PrivacyConfig] {
+        &self.privacy
+    }
+}
+
@@ -0,0 +1,621 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use log::LevelFilter;
+use serde::Deserialize;
+use where_rs::config::Protocol;
+use crate::args::Args;
+use crate::passwd;
+
+const CONFIG_PATH: &str = "/etc/whered.toml";
+const CONTROL_PATH: &str = "/run/whered.sock";
+const CONTROL_MODE: u32 = 0o600;
+const LOCAL_PATH: &str = "/run/whered-query.sock";
+const LOCAL_MODE: u32 = 0o666;
+const REGISTRATION_PATH: &str = "/run/whered-register.sock";
+const REGISTRATION_MODE: u32 = 0o600;
+const REGISTRATION_TTL: u64 = 300;
+const MAX_REGISTRATION_TTL: u64 = 3600;
+const MAX_REGISTERED_SESSIONS: usize = 256;
+const RELAY_INTERVAL: u64 = 10;
+const RELAY_MAX_AGE: u64 = 60;
+const LOG_PATH: &str = "/var/log/whered.log";
+const AUDIT_PATH: &str = "/var/log/whered/audit.log";
+const AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const AUDIT_MAX_FILES: usize = 5;
+const OPT_OUT_MARKER: &str = ".nowhere";
+const STATUS_FILE: &str = ".plan";
+const MAX_STATUS_LENGTH: usize = 128;
+const IPV4_PREFIX: u8 = 24;
+const IPV6_PREFIX: u8 = 48;
+const METRICS_LISTEN_ADDR: &str = "127.0.0.1:9115";
+const FINGER_LISTEN_ADDR: &str = "0.0.0.0:79";
+const TCP_LISTEN_ADDR: &str = "0.0.0.0:15";
+const RWHO_LISTEN_ADDR: &str = "0.0.0.0:513";
+const RWHO_DESTINATION: &str = "255.255.255.255:513";
+const RWHO_INTERVAL: u64 = 180;
+const LISTEN_ADDR: &str = "0.0.0.0:15";
+const WORKERS: usize = 4;
+const QUEUE_LENGTH: usize = 64;
+const MAX_CONNECTIONS: usize = 64;
+const MAX_SUBSCRIBERS: usize = 32;
+const MAX_LEASE: u32 = 300;
+const POLL_INTERVAL: u64 = 500;
+const CACHE_TTL: u64 = 1000;
+#[cfg(target_os = "macos")]
+const UTMP_PATH: &str = "/var/run/utmpx";
+#[cfg(not(target_os = "macos"))]
+const UTMP_PATH: &str = "/var/run/utmp";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub listen: Vec<ListenConfig>,
+    pub server: ServerConfig,
+    pub subscriptions: SubscriptionConfig,
+    pub cache: CacheConfig,
+    pub privileges: PrivilegeConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub control: ControlConfig,
+    pub audit: AuditConfig,
+    pub privacy: Vec<PrivacyConfig>,
+    pub opt_out: OptOutConfig,
+    pub details: DetailsConfig,
+    pub finger: FingerConfig,
+    pub rwho: RwhoConfig,
+    pub tcp: TcpConfig,
+    pub local: LocalConfig,
+    pub registration: RegistrationConfig,
+    pub relay: RelayConfig
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ListenConfig {
+    pub address: String,
+    pub ipv6_only: Option<bool>,
+    pub interface: Option<String>
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub workers: usize,
+    pub queue_length: usize,
+    pub max_connections: usize,
+    pub idle_timeout: u64
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SubscriptionConfig {
+    pub enabled: bool,
+    pub max_subscribers: usize,
+    pub max_lease: u32,
+    pub poll_interval: u64
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            workers: WORKERS,
+            queue_length: QUEUE_LENGTH,
+            max_connections: MAX_CONNECTIONS,
+            idle_timeout: 0
+        }
+    }
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_subscribers: MAX_SUBSCRIBERS,
+            max_lease: MAX_LEASE,
+            poll_interval: POLL_INTERVAL
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub utmp_path: PathBuf,
+    pub fixture: Option<PathBuf>,
+    pub ttl: u64
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            utmp_path: PathBuf::from(UTMP_PATH),
+            fixture: None,
+            ttl: CACHE_TTL
+        }
+    }
+}
+
+impl CacheConfig {
+    /// The file sessions are read from, which is watched for changes.
+    pub fn source_path(&self) -> &Path {
+        self.fixture.as_deref().unwrap_or(&self.utmp_path)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PrivilegeConfig {
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub chroot: Option<PathBuf>,
+    pub landlock: bool,
+    pub readable_paths: Vec<PathBuf>,
+    pub writable_paths: Vec<PathBuf>,
+    pub allow_root: bool
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    Stderr,
+    File,
+    Syslog,
+    Journald
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub output: LogOutput,
+    pub path: PathBuf
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+            output: LogOutput::Stderr,
+            path: PathBuf::from(LOG_PATH)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub address: String
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: METRICS_LISTEN_ADDR.to_string()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub mode: u32
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from(CONTROL_PATH),
+            mode: CONTROL_MODE
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LocalConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub mode: u32,
+    pub group: Option<String>
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from(LOCAL_PATH),
+            mode: LOCAL_MODE,
+            group: None
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RegistrationConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub mode: u32,
+    pub group: Option<String>,
+    pub default_ttl: u64,
+    pub max_ttl: u64,
+    pub max_sessions: usize
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from(REGISTRATION_PATH),
+            mode: REGISTRATION_MODE,
+            group: None,
+            default_ttl: REGISTRATION_TTL,
+            max_ttl: MAX_REGISTRATION_TTL,
+            max_sessions: MAX_REGISTERED_SESSIONS
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub enabled: bool,
+    pub interval: u64,
+    pub max_age: u64,
+    pub global: where_rs::config::GlobalConfig,
+    pub server: Vec<where_rs::config::Server>
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: RELAY_INTERVAL,
+            max_age: RELAY_MAX_AGE,
+            global: where_rs::config::GlobalConfig::default(),
+            server: vec![]
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_files: usize
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from(AUDIT_PATH),
+            max_size: AUDIT_MAX_SIZE,
+            max_files: AUDIT_MAX_FILES
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteRedaction {
+    #[default]
+    Keep,
+    Drop,
+    Prefix,
+    Hash
+}
+
+/// A network in CIDR notation, such as "10.0.0.0/8".  A single address matches only itself.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String")]
+pub struct Network {
+    pub address: IpAddr,
+    pub prefix: u8
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    pub networks: Vec<Network>,
+    pub exclude_users: Vec<String>,
+    pub exclude_uids: Vec<[u32; 2]>,
+    pub exclude_ttys: Vec<String>,
+    pub remote: RemoteRedaction,
+    pub ipv4_prefix: u8,
+    pub ipv6_prefix: u8,
+    pub hash_key: String
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            networks: vec![],
+            exclude_users: vec![],
+            exclude_uids: vec![],
+            exclude_ttys: vec![],
+            remote: RemoteRedaction::Keep,
+            ipv4_prefix: IPV4_PREFIX,
+            ipv6_prefix: IPV6_PREFIX,
+            hash_key: String::new()
+        }
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.as_str(), None)
+        };
+
+        let address = IpAddr::from_str(address).map_err(|e| format!("Invalid network {value}: {e}"))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or(format!("Invalid prefix length in network {value}"))?,
+            None => max_prefix
+        };
+
+        Ok(Self {
+            address,
+            prefix
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OptOutAction {
+    Omit,
+    Anonymize
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OptOutConfig {
+    pub enabled: bool,
+    pub marker: String,
+    pub list: Option<PathBuf>,
+    pub action: OptOutAction
+}
+
+impl Default for OptOutConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            marker: OPT_OUT_MARKER.to_string(),
+            list: None,
+            action: OptOutAction::Omit
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DetailsConfig {
+    pub real_name: bool,
+    pub status: bool,
+    pub status_file: String,
+    pub max_status_length: usize
+}
+
+impl Default for DetailsConfig {
+    fn default() -> Self {
+        Self {
+            real_name: false,
+            status: false,
+            status_file: STATUS_FILE.to_string(),
+            max_status_length: MAX_STATUS_LENGTH
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FingerConfig {
+    pub enabled: bool,
+    pub address: String
+}
+
+impl Default for FingerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: FINGER_LISTEN_ADDR.to_string()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TcpConfig {
+    pub enabled: bool,
+    pub address: String
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: TCP_LISTEN_ADDR.to_string()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RwhoConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub destinations: Vec<String>,
+    pub interval: u64,
+    pub hostname: Option<String>
+}
+
+impl Default for RwhoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: RWHO_LISTEN_ADDR.to_string(),
+            destinations: vec![RWHO_DESTINATION.to_string()],
+            interval: RWHO_INTERVAL,
+            hostname: None
+        }
+    }
+}
+
+impl ListenConfig {
+    pub fn from_address(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            ipv6_only: None,
+            interface: None
+        }
+    }
+}
+
+impl Config {
+    pub fn build(args: &Args) -> Self {
+        Self::load(args).unwrap_or_else(|e| {
+            eprintln!("whered: {e}");
+            std::process::exit(1);
+        })
+    }
+
+    /// Reads the configuration file and applies the command line options over it.
+    pub fn load(args: &Args) -> Result<Self, String> {
+        let mut config = Self::read(args)?;
+
+        // Addresses given on the command line replace the configured ones
+        if !args.listen_addr.is_empty() {
+            config.listen = args.listen_addr.iter()
+                .map(|address| ListenConfig::from_address(address))
+                .collect();
+        } else if config.listen.is_empty() {
+            config.listen.push(ListenConfig::from_address(LISTEN_ADDR));
+        }
+
+        if args.user.is_some() {
+            config.privileges.user = args.user.clone();
+        }
+
+        if args.group.is_some() {
+            config.privileges.group = args.group.clone();
+        }
+
+        if let Some(level) = args.log_level {
+            config.log.level = level;
+        }
+
+        if let Some(index) = config.privacy.iter().position(|rule| rule.remote == RemoteRedaction::Hash && rule.hash_key.is_empty()) {
+            return Err(format!("Privacy rule set {} hashes remotes, so it needs a hash_key", index + 1));
+        }
+
+        // Landlock doesn't allow running anything, and which files a command needs can't be known
+        let runs_commands = config.relay.enabled && config.relay.server.iter()
+            .any(|server| matches!(server.protocol, Protocol::Command | Protocol::Stdio));
+
+        if config.privileges.landlock && runs_commands {
+            return Err("Relayed servers with the command or stdio protocol can't be queried with privileges.landlock enabled".to_string());
+        }
+
+        Ok(config)
+    }
+
+    /// Lists the paths whered needs to read from once it is running, for Landlock.
+    pub fn readable_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from("/etc")];
+
+        // Read again on SIGHUP
+        if self.path.exists() {
+            paths.push(self.path.clone());
+        }
+
+        if let Some(directory) = self.cache.source_path().parent().filter(|path| !path.as_os_str().is_empty()) {
+            paths.push(directory.to_path_buf());
+        }
+
+        if let Some(list) = self.opt_out.list.as_ref().filter(|_| self.opt_out.enabled) {
+            paths.push(list.clone());
+        }
+
+        // Opt-out markers and status files are in home directories, and users created later
+        // on need a restart
+        let opt_out_markers = self.opt_out.enabled && !self.opt_out.marker.is_empty();
+        let status_files = self.details.status && !self.details.status_file.is_empty();
+
+        if opt_out_markers || status_files {
+            paths.extend(passwd::home_directories().into_iter().filter(|home| home.is_dir()));
+        }
+
+        // Spool directories of rwho servers that are relayed
+        if self.relay.enabled {
+            paths.extend(self.relay.server.iter()
+                .filter(|server| server.protocol == Protocol::Rwho)
+                .map(|server| PathBuf::from(&server.endpoint)));
+        }
+
+        paths.extend(self.privileges.readable_paths.iter().cloned());
+        paths
+    }
+
+    /// Lists the paths whered needs to write to once it is running.
+    pub fn writable_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.privileges.writable_paths.clone();
+
+        if self.log.output == LogOutput::File {
+            paths.push(self.log.path.clone());
+        }
+
+        // Rotating the audit log creates and renames files next to it
+        if let Some(directory) = self.audit.path.parent().filter(|path| self.audit.enabled && !path.as_os_str().is_empty()) {
+            paths.push(directory.to_path_buf());
+        }
+
+        paths
+    }
+
+    fn read(args: &Args) -> Result<Self, String> {
+        let path = args.config.clone().unwrap_or(PathBuf::from(CONFIG_PATH));
+
+        let config = match fs::read_to_string(&path) {
+            Ok(str) => toml::from_str(&str)
+                .map_err(|e| format!("Failed to parse configuration file: {e}"))?,
+            // The configuration file is optional unless it was explicitly given
+            Err(e) if e.kind() == ErrorKind::NotFound && args.config.is_none() => Self::default(),
+            Err(e) => return Err(format!("Failed to read configuration file {}: {e}", path.display()))
+        };
+
+        Ok(Self {
+            path,
+            ..config
+        })
+    }
+}
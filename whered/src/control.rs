@@ -0,0 +1,183 @@
+use std::fmt::Write as _;
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use chrono::DateTime;
+use log::{debug, info};
+use crate::args::{Args, ControlCommand};
+use crate::config::ControlConfig;
+use crate::metrics::METRICS;
+use crate::server::Server;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+const ERROR_PREFIX: &str = "error: ";
+
+impl ControlCommand {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Stats => "stats",
+            Self::Subscribers => "subscribers",
+            Self::Cache => "cache",
+            Self::Reload => "reload",
+            Self::Drain => "drain",
+            Self::Stop => "stop"
+        }
+    }
+
+    fn from_str(command: &str) -> Option<Self> {
+        [Self::Stats, Self::Subscribers, Self::Cache, Self::Reload, Self::Drain, Self::Stop].into_iter()
+            .find(|c| c.as_str() == command)
+    }
+}
+
+/// Creates the control socket, replacing the one a previous instance may have left behind.
+/// This has to be done before dropping privileges, as the socket usually lives in /run.
+pub fn bind(config: &ControlConfig) -> io::Result<UnixListener> {
+    bind_socket(&config.path, config.mode)
+}
+
+/// Creates a Unix socket with the given permissions, replacing a stale one.
+pub fn bind_socket(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display()))),
+        Err(e) if e.kind() == ErrorKind::NotFound => {},
+        Err(e) => return Err(e)
+    }
+
+    // Until its permissions are set, only the owner may connect to the socket
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+
+    let listener = listener?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+
+    Ok(listener)
+}
+
+/// Answers admin commands, one per connection.
+pub fn start_listener(listener: UnixListener, server: Arc<Server>, args: Args) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let res = stream.and_then(|stream| {
+                stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+                stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+                handle_connection(stream, &server, &args)
+            });
+
+            if let Err(e) = res {
+                debug!("Control socket: {e}");
+            }
+        }
+    });
+}
+
+fn handle_connection(mut stream: UnixStream, server: &Server, args: &Args) -> io::Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+
+    let response = match ControlCommand::from_str(line.trim()) {
+        Some(command) => {
+            info!("Control socket: {}", command.as_str());
+            run_command(command, server, args)
+        },
+        None => Err(format!("Unknown command {:?}", line.trim()))
+    };
+
+    match response {
+        Ok(response) => stream.write_all(response.as_bytes()),
+        Err(e) => writeln!(stream, "{ERROR_PREFIX}{e}")
+    }
+}
+
+fn run_command(command: ControlCommand, server: &Server, args: &Args) -> Result<String, String> {
+    let mut response = String::new();
+
+    match command {
+        ControlCommand::Stats => {
+            let subscribers = server.subscriptions().lock().unwrap().subscribers().len();
+
+            for (name, value) in [
+                ("uptime_seconds", server.uptime().as_secs()),
+                ("requests_served", METRICS.requests_served.load(Ordering::Relaxed)),
+                ("requests_rejected", METRICS.requests_rejected.load(Ordering::Relaxed)),
+                ("requests_rate_limited", METRICS.requests_rate_limited.load(Ordering::Relaxed)),
+                ("sent_bytes", METRICS.bytes_sent.load(Ordering::Relaxed)),
+                ("encode_errors", METRICS.encode_errors.load(Ordering::Relaxed)),
+                ("responses_truncated", METRICS.responses_truncated.load(Ordering::Relaxed)),
+                ("sessions", METRICS.sessions.load(Ordering::Relaxed)),
+                ("utmp_reads", METRICS.utmp_reads.load(Ordering::Relaxed)),
+                ("subscribers", subscribers as u64)
+            ] {
+                writeln!(response, "{name}: {value}").unwrap();
+            }
+        },
+        ControlCommand::Subscribers => {
+            for (addr, remaining) in server.subscriptions().lock().unwrap().leases() {
+                writeln!(response, "{addr} expires in {}s", remaining.as_secs()).unwrap();
+            }
+        },
+        ControlCommand::Cache => {
+            let snapshot = server.cache().snapshot();
+
+            writeln!(response, "generation {}, read {} ms ago, {} sessions",
+                     snapshot.generation, snapshot.age().as_millis(), snapshot.sessions.len()).unwrap();
+
+            for session in snapshot.sessions.clone().into_vec() {
+                let login_time = DateTime::from_timestamp(session.login_time, 0)
+                    .map_or(session.login_time.to_string(), |time| time.to_rfc3339());
+
+                writeln!(response, "{} {} {} pid {} since {login_time}{}",
+                         session.user, session.tty, session.remote.as_deref().unwrap_or("-"), session.pid,
+                         if session.active { "" } else { " (ended)" }).unwrap();
+            }
+        },
+        ControlCommand::Reload => {
+            server.reload(args)?;
+            response.push_str("Reloaded configuration\n");
+        },
+        ControlCommand::Drain => {
+            let subscribers = server.drain();
+            writeln!(response, "Draining, waiting for {subscribers} subscriptions to expire").unwrap();
+        },
+        ControlCommand::Stop => {
+            server.shutdown();
+            response.push_str("Shutting down\n");
+        }
+    }
+
+    Ok(response)
+}
+
+/// Sends a command to a running whered and prints its response.
+pub fn run_client(path: &Path, command: ControlCommand) -> Result<(), String> {
+    let connect = || -> io::Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+
+        writeln!(stream, "{}", command.as_str())?;
+        stream.shutdown(Shutdown::Write)?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+
+    let response = connect().map_err(|e| format!("Failed to reach whered through {}: {e}", path.display()))?;
+
+    match response.strip_prefix(ERROR_PREFIX) {
+        Some(e) => Err(e.trim_end().to_string()),
+        None => {
+            print!("{response}");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind, Read};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::Path;
+use whrd::{Session, SessionCollection, MAX_REAL_NAME_LENGTH, MAX_STATUS_LENGTH};
+use crate::config::DetailsConfig;
+use crate::passwd;
+
+impl DetailsConfig {
+    /// Adds the real name and status message of every user, as far as they are enabled and
+    /// available.
+    pub fn apply(&self, sessions: SessionCollection) -> SessionCollection {
+        if !self.real_name && !self.status {
+            return sessions;
+        }
+
+        let mut details = HashMap::new();
+
+        let sessions = sessions.into_vec().into_iter()
+            .map(|session| {
+                let (real_name, status) = details.entry(session.user.clone())
+                    .or_insert_with(|| self.lookup(&session.user))
+                    .clone();
+
+                Session {
+                    real_name,
+                    status,
+                    ..session
+                }
+            })
+            .collect();
+
+        SessionCollection::from_vec(sessions)
+    }
+
+    fn lookup(&self, user: &str) -> (Option<String>, Option<String>) {
+        let Some(entry) = passwd::lookup(user) else {
+            return (None, None);
+        };
+
+        // The other comma-separated GECOS fields are phone numbers and such
+        let real_name = entry.gecos.split(',').next()
+            .map(str::trim)
+            .filter(|name| self.real_name && !name.is_empty())
+            .map(|name| truncate(name, MAX_REAL_NAME_LENGTH));
+
+        // Only the first line is used, like a status rather than a whole plan
+        let status = Some(entry.home.join(&self.status_file))
+            .filter(|_| self.status && !self.status_file.is_empty())
+            .and_then(|path| read_status_file(&path, entry.uid).ok())
+            .and_then(|plan| plan.lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_string))
+            .map(|line| truncate(&line, self.max_status_length.min(MAX_STATUS_LENGTH)));
+
+        (real_name, status)
+    }
+}
+
+/// Reads the beginning of a status file.  Users control it, so it has to be a regular file
+/// they own rather than a link to someone else's file, a FIFO that never ends or a device
+/// that never stops, as the cache is locked in the meantime.
+fn read_status_file(path: &Path, uid: u32) -> io::Result<String> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)?;
+    let metadata = file.metadata()?;
+
+    if !metadata.is_file() || metadata.uid() != uid {
+        return Err(io::Error::new(ErrorKind::PermissionDenied, "Not a regular file owned by the user"));
+    }
+
+    let mut buffer = Vec::new();
+    file.take(MAX_STATUS_LENGTH as u64).read_to_end(&mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Shortens a string to at most `max_length` bytes, without cutting a character in half.
+fn truncate(string: &str, max_length: usize) -> String {
+    let mut end = string.len().min(max_length);
+
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    string[..end].to_string()
+}
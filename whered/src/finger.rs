@@ -0,0 +1,174 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use log::debug;
+use whrd::Session;
+use crate::audit::{AuditRecord, Outcome};
+use crate::metrics::{Metrics, METRICS};
+use crate::pool::ConnectionLimit;
+use crate::server::Server;
+use crate::tcp;
+
+const FINGER_TIMEOUT: Duration = Duration::from_secs(5);
+// Longer queries are not something a finger client would send
+const MAX_QUERY_LENGTH: u64 = 512;
+
+/// A query as described by RFC 1288.
+struct Query {
+    verbose: bool,
+    user: Option<String>,
+    forwarded: bool
+}
+
+impl Query {
+    fn parse(line: &str) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']).trim();
+
+        let (verbose, rest) = match line.strip_prefix("/W") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, line)
+        };
+
+        Self {
+            verbose,
+            user: Some(rest.to_string()).filter(|user| !user.is_empty()),
+            forwarded: rest.contains('@')
+        }
+    }
+}
+
+/// Answers finger queries with the same sessions and privacy rules as WHRD requests.
+pub fn start_listener(listener: TcpListener, server: Arc<Server>, max_connections: usize) {
+    let limit = ConnectionLimit::new(max_connections);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Finger: {e}");
+                    continue;
+                }
+            };
+
+            let res = limit.spawn(stream, {
+                let server = server.clone();
+
+                move |stream| {
+                    if let Err(e) = handle_connection(stream, &server) {
+                        debug!("Finger: {e}");
+                    }
+                }
+            });
+
+            if let Err(stream) = res {
+                tcp::rate_limited(&server, stream.peer_addr().ok());
+            }
+        }
+    });
+}
+
+fn handle_connection(mut stream: TcpStream, server: &Server) -> io::Result<()> {
+    stream.set_read_timeout(Some(FINGER_TIMEOUT))?;
+    stream.set_write_timeout(Some(FINGER_TIMEOUT))?;
+
+    let src = stream.peer_addr()?;
+    let mut line = String::new();
+    BufReader::new(&stream).take(MAX_QUERY_LENGTH).read_line(&mut line)?;
+
+    let query = Query::parse(&line);
+
+    if query.forwarded {
+        audit(server, src, &query, None, Outcome::Refused);
+        return stream.write_all(b"finger: forwarding service denied\r\n");
+    }
+
+    // Relayed sessions are not on this host, and finger has no way to tell where they are
+    let sessions: Vec<Session> = server.sessions_for(src.ip()).into_vec().into_iter()
+        .filter(|session| session.active && session.host.is_none())
+        .filter(|session| query.user.as_ref().is_none_or(|user| session.user == *user))
+        .collect();
+
+    let response = match &query.user {
+        _ if !sessions.is_empty() => format_listing(&sessions, query.verbose || query.user.is_some()),
+        Some(user) => format!("finger: {}: no such user, or not logged in\r\n", sanitize(user)),
+        None => "No one logged on.\r\n".to_string()
+    };
+
+    audit(server, src, &query, Some(sessions.len()), Outcome::Served);
+    debug!("{src}: Answered finger query for {} sessions", sessions.len());
+
+    stream.write_all(response.as_bytes())?;
+    Metrics::increment(&METRICS.requests_served);
+    Metrics::add(&METRICS.bytes_sent, response.len() as u64);
+
+    Ok(())
+}
+
+fn audit(server: &Server, source: SocketAddr, query: &Query, sessions: Option<usize>, outcome: Outcome) {
+    server.audit_record(AuditRecord {
+        source,
+        request: Some("finger"),
+        filters: query.user.iter().map(|user| format!("user={user}")).collect(),
+        sessions,
+        outcome
+    });
+}
+
+/// Formats sessions like who(1) does, with the real name and status when they are known.
+fn format_listing(sessions: &[Session], with_status: bool) -> String {
+    let rows: Vec<[String; 6]> = sessions.iter()
+        .map(|session| {
+            let login_time = DateTime::from_timestamp(session.login_time, 0)
+                .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+
+            [
+                session.user.clone(),
+                session.real_name.clone().unwrap_or_default(),
+                session.tty.clone(),
+                login_time,
+                session.remote.clone().unwrap_or_default(),
+                session.status.clone().filter(|_| with_status).unwrap_or_default()
+            ].map(|field| sanitize(&field))
+        })
+        .collect();
+
+    let header = ["Login", "Name", "TTY", "Login Time", "Where", "Status"];
+    // Columns nobody has anything in are left out
+    let columns: Vec<usize> = (0..header.len())
+        .filter(|column| matches!(column, 0 | 2 | 3) || rows.iter().any(|row| !row[*column].is_empty()))
+        .collect();
+
+    let widths: Vec<usize> = columns.iter()
+        .map(|column| rows.iter().map(|row| row[*column].chars().count()).max().unwrap_or(0).max(header[*column].len()))
+        .collect();
+
+    let format_row = |fields: &[&str]| {
+        let line = columns.iter().zip(&widths)
+            .map(|(column, width)| format!("{:<width$}", fields[*column]))
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        format!("{}\r\n", line.trim_end())
+    };
+
+    let mut listing = format_row(&header);
+
+    for row in &rows {
+        listing.push_str(&format_row(&row.each_ref().map(String::as_str)));
+    }
+
+    listing
+}
+
+/// Replaces control characters, so that a status message can't send escape sequences to
+/// the terminal of whoever runs finger.
+fn sanitize(field: &str) -> String {
+    field.chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect()
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use whrd::{Session, SessionCollection, MAX_ORIGIN_LENGTH, MAX_REAL_NAME_LENGTH, MAX_REMOTE_LENGTH, MAX_STATUS_LENGTH, MAX_USER_TTY_LENGTH};
+
+/// Sessions described in a file, served instead of the ones in utmp for tests and demos.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Fixture {
+    session: Vec<FixtureSession>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureSession {
+    user: String,
+    tty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    remote: Option<String>,
+    #[serde(default)]
+    pid: i32,
+    #[serde(default)]
+    login_time: i64,
+    #[serde(default = "active")]
+    active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    real_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>
+}
+
+fn active() -> bool {
+    true
+}
+
+/// Reads sessions from a fixture, in TOML if its name ends with ".toml" and in JSON otherwise.
+pub fn load(path: &Path) -> io::Result<SessionCollection> {
+    let contents = fs::read_to_string(path)?;
+
+    let fixture: Fixture = if path.extension().is_some_and(|extension| extension == "toml") {
+        toml::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e.message().to_string()))?
+    } else {
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+    };
+
+    let sessions = fixture.session.into_iter()
+        .enumerate()
+        .map(|(index, session)| session.check().map_err(|e| {
+            io::Error::new(ErrorKind::InvalidData, format!("Session {}: {e}", index + 1))
+        }))
+        .collect::<io::Result<Vec<Session>>>()?;
+
+    Ok(SessionCollection::from_vec(sessions))
+}
+
+/// Describes sessions in JSON, so that they can be served again later as a fixture.
+pub fn record(sessions: SessionCollection) -> String {
+    let fixture = Fixture {
+        session: sessions.into_vec().into_iter().map(FixtureSession::from).collect()
+    };
+
+    serde_json::to_string_pretty(&fixture).unwrap()
+}
+
+impl FixtureSession {
+    /// Turns the description into a session, if it fits in a WHRD entry.
+    fn check(self) -> Result<Session, String> {
+        for (name, value, max_length) in [
+            ("user", Some(&self.user), MAX_USER_TTY_LENGTH),
+            ("tty", Some(&self.tty), MAX_USER_TTY_LENGTH),
+            ("remote", self.remote.as_ref(), MAX_REMOTE_LENGTH),
+            ("real_name", self.real_name.as_ref(), MAX_REAL_NAME_LENGTH),
+            ("status", self.status.as_ref(), MAX_STATUS_LENGTH),
+            ("origin", self.origin.as_ref(), MAX_ORIGIN_LENGTH)
+        ] {
+            if value.is_some_and(|value| value.len() > max_length) {
+                return Err(format!("{name} must be at most {max_length} bytes long"));
+            }
+        }
+
+        Ok(Session {
+            host: None,
+            pid: self.pid,
+            login_time: self.login_time,
+            user: self.user,
+            tty: self.tty,
+            remote: self.remote,
+            active: self.active,
+            real_name: self.real_name,
+            status: self.status,
+            origin: self.origin
+        })
+    }
+}
+
+impl From<Session> for FixtureSession {
+    fn from(session: Session) -> Self {
+        Self {
+            user: session.user,
+            tty: session.tty,
+            remote: session.remote,
+            pid: session.pid,
+            login_time: session.login_time,
+            active: session.active,
+            real_name: session.real_name,
+            status: session.status,
+            origin: session.origin
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use super::*;
+
+    fn write_fixture(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("whered-{}-{name}", process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn recorded_sessions_load_back() {
+        let sessions = SessionCollection::from_vec(vec![Session {
+            host: None,
+            pid: 1234,
+            login_time: 1700000000,
+            user: "alice".to_string(),
+            tty: "pts/0".to_string(),
+            remote: Some("10.0.0.1".to_string()),
+            active: true,
+            real_name: Some("Alice".to_string()),
+            status: None,
+            origin: Some("webterm".to_string())
+        }]);
+
+        let recorded = record(sessions);
+        let path = write_fixture("recorded.json", &recorded);
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(record(loaded.unwrap()), recorded);
+    }
+
+    #[test]
+    fn toml_fixtures_fill_in_defaults() {
+        let path = write_fixture("defaults.toml", "[[session]]\nuser = \"bob\"\ntty = \"tty1\"\n");
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let sessions = loaded.unwrap().into_vec();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!((sessions[0].pid, sessions[0].login_time, sessions[0].active), (0, 0, true));
+        assert!(sessions[0].remote.is_none());
+    }
+
+    #[test]
+    fn sessions_that_do_not_fit_are_rejected() {
+        let user = "a".repeat(MAX_USER_TTY_LENGTH + 1);
+        let path = write_fixture("long.json", &format!(r#"{{"session": [{{"user": "{user}", "tty": "pts/0"}}]}}"#));
+        let loaded = load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,53 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use socket2::{Domain, Protocol, Socket, Type};
+use whrd::error::WhereResult;
+use crate::config::ListenConfig;
+
+impl ListenConfig {
+    pub fn bind(&self) -> WhereResult<UdpSocket> {
+        let address = SocketAddr::from_str(&self.address)?;
+        let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+
+        if let Some(ipv6_only) = self.ipv6_only {
+            if address.is_ipv6() {
+                socket.set_only_v6(ipv6_only)?;
+            }
+        }
+
+        if let Some(interface) = &self.interface {
+            bind_interface(&socket, &address, interface)?;
+        }
+
+        socket.bind(&address.into())?;
+
+        Ok(socket.into())
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_interface(socket: &Socket, _address: &SocketAddr, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(any(target_os = "ios", target_os = "macos", target_os = "tvos", target_os = "watchos"))]
+fn bind_interface(socket: &Socket, address: &SocketAddr, interface: &str) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::num::NonZeroU32;
+
+    let name = CString::new(interface).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let index = NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No such network interface: {interface}")))?;
+
+    if address.is_ipv4() {
+        socket.bind_device_by_index_v4(Some(index))
+    } else {
+        socket.bind_device_by_index_v6(Some(index))
+    }
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux", target_os = "ios", target_os = "macos", target_os = "tvos", target_os = "watchos")))]
+fn bind_interface(_socket: &Socket, _address: &SocketAddr, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Binding to a network interface is not supported on this system"))
+}
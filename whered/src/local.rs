@@ -0,0 +1,66 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::unix::fs as unix_fs;
+use std::os::unix::net::UnixListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use log::debug;
+use crate::config::LocalConfig;
+use crate::control;
+use crate::pool::ConnectionLimit;
+use crate::privileges;
+use crate::server::Server;
+use crate::tcp;
+
+const LOCAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Creates the local query socket.  Its group and permissions decide who can query it, and
+/// they have to be set before dropping privileges.
+pub fn bind(config: &LocalConfig) -> io::Result<UnixListener> {
+    let listener = control::bind_socket(&config.path, config.mode)?;
+
+    if let Some(group) = &config.group {
+        unix_fs::chown(&config.path, None, Some(privileges::lookup_group(group)?))?;
+    }
+
+    Ok(listener)
+}
+
+/// Answers requests from local tools, framed like over TCP, one per connection.  They see
+/// the sessions a client on 127.0.0.1 would.
+pub fn start_listener(listener: UnixListener, server: Arc<Server>, max_connections: usize) {
+    let src = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+    let limit = ConnectionLimit::new(max_connections);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Local socket: {e}");
+                    continue;
+                }
+            };
+
+            let res = limit.spawn(stream, {
+                let server = server.clone();
+
+                move |mut stream| {
+                    let res = stream.set_read_timeout(Some(LOCAL_TIMEOUT))
+                        .and_then(|_| stream.set_write_timeout(Some(LOCAL_TIMEOUT)))
+                        .map_err(Into::into)
+                        .and_then(|_| tcp::answer(&mut stream, src, &server).map(drop));
+
+                    if let Err(e) = res {
+                        debug!("Local socket: {e}");
+                    }
+                }
+            });
+
+            if res.is_err() {
+                tcp::rate_limited(&server, Some(src));
+            }
+        }
+    });
+}
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use chrono::{SecondsFormat, Utc};
+use log::{warn, Level, Log, Metadata, Record};
+use serde_json::json;
+use crate::config::{LogConfig, LogFormat, LogOutput};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+enum Output {
+    Stderr,
+    File(File),
+    Syslog,
+    Journald(UnixDatagram)
+}
+
+pub struct Logger {
+    format: LogFormat,
+    kind: LogOutput,
+    path: PathBuf,
+    output: Mutex<Output>
+}
+
+impl Logger {
+    /// Installs the logger for the whole process.  Everything that needs a connection or a file
+    /// is opened right away, so that logging keeps working after privileges are dropped.
+    pub fn init(config: &LogConfig) -> io::Result<()> {
+        let output = match config.output {
+            LogOutput::Stderr => Output::Stderr,
+            LogOutput::File => Output::File(Self::open_file(&config.path)?),
+            LogOutput::Syslog => {
+                // openlog keeps a pointer to the identifier, so it has to live forever
+                static IDENT: OnceLock<CString> = OnceLock::new();
+                let ident = IDENT.get_or_init(|| CString::new("whered").unwrap());
+
+                unsafe { libc::openlog(ident.as_ptr(), libc::LOG_PID | libc::LOG_NDELAY, libc::LOG_DAEMON) };
+                Output::Syslog
+            },
+            LogOutput::Journald => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(JOURNALD_SOCKET)?;
+                Output::Journald(socket)
+            }
+        };
+
+        let logger = LOGGER.get_or_init(|| Self {
+            format: config.format,
+            kind: config.output,
+            path: config.path.clone(),
+            output: Mutex::new(output)
+        });
+
+        log::set_logger(logger).map_err(|e| io::Error::new(io::ErrorKind::AlreadyExists, e))?;
+        log::set_max_level(config.level);
+
+        Ok(())
+    }
+
+    /// Opens the log file again, so that it can be rotated.  The new level is applied to every
+    /// output, but a new output, format or path only applies after a restart, as it may well
+    /// be out of reach once privileges are dropped.
+    pub fn reopen(config: &LogConfig) -> Result<(), String> {
+        log::set_max_level(config.level);
+
+        let Some(logger) = LOGGER.get() else {
+            return Ok(());
+        };
+
+        if config.output != logger.kind || config.format != logger.format || (logger.kind == LogOutput::File && config.path != logger.path) {
+            warn!("Changes to the log output, format or path need a restart, logging as before until then");
+        }
+
+        let mut output = logger.output.lock().unwrap();
+
+        if let Output::File(_) = *output {
+            let file = Self::open_file(&logger.path).map_err(|e| format!("Failed to reopen {}: {e}", logger.path.display()))?;
+            *output = Output::File(file);
+        }
+
+        Ok(())
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn format_message(&self, record: &Record) -> String {
+        match self.format {
+            LogFormat::Text => record.args().to_string(),
+            LogFormat::Json => json!({
+                "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                "level": record.level().as_str().to_lowercase(),
+                "target": record.target(),
+                "message": record.args().to_string()
+            }).to_string()
+        }
+    }
+
+    fn write_journald(socket: &UnixDatagram, record: &Record, message: &str) -> io::Result<()> {
+        let mut datagram = vec![];
+
+        for (field, value) in [
+            ("PRIORITY", syslog_priority(record.level()).to_string().as_str()),
+            ("SYSLOG_IDENTIFIER", "whered"),
+            ("MESSAGE", message)
+        ] {
+            datagram.extend(field.as_bytes());
+
+            if value.contains('\n') {
+                // Values with newlines have to be sent with their length instead
+                datagram.push(b'\n');
+                datagram.extend((value.len() as u64).to_le_bytes());
+            } else {
+                datagram.push(b'=');
+            }
+
+            datagram.extend(value.as_bytes());
+            datagram.push(b'\n');
+        }
+
+        socket.send(&datagram)?;
+        Ok(())
+    }
+}
+
+fn syslog_priority(level: Level) -> libc::c_int {
+    match level {
+        Level::Error => libc::LOG_ERR,
+        Level::Warn => libc::LOG_WARNING,
+        Level::Info => libc::LOG_INFO,
+        Level::Debug | Level::Trace => libc::LOG_DEBUG
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = self.format_message(record);
+        let mut output = self.output.lock().unwrap();
+
+        // There is nowhere left to report a failure to log, so it is ignored
+        let _ = match &mut *output {
+            Output::Stderr => match self.format {
+                LogFormat::Text => writeln!(io::stderr(), "whered: {}: {message}", record.level().as_str().to_lowercase()),
+                LogFormat::Json => writeln!(io::stderr(), "{message}")
+            },
+            Output::File(file) => match self.format {
+                LogFormat::Text => writeln!(file, "{} {}: {message}", Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), record.level().as_str().to_lowercase()),
+                LogFormat::Json => writeln!(file, "{message}")
+            },
+            Output::Syslog => {
+                let message = CString::new(message.replace('\0', " ")).unwrap();
+                unsafe { libc::syslog(syslog_priority(record.level()), c"%s".as_ptr(), message.as_ptr()) };
+                Ok(())
+            },
+            Output::Journald(socket) => Self::write_journald(socket, record, &message)
+        };
+    }
+
+    fn flush(&self) {
+        if let Output::File(file) = &mut *self.output.lock().unwrap() {
+            let _ = file.flush();
+        }
+    }
+}
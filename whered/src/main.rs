@@ -1,55 +1,233 @@
+mod activation;
 mod args;
+mod audit;
+mod cache;
+mod config;
+mod control;
+mod details;
+mod finger;
+mod fixture;
+#[cfg(target_os = "linux")]
+mod landlock;
+mod listen;
+mod local;
+mod logger;
+mod metrics;
+mod notify;
+mod optout;
+mod passwd;
+mod pool;
+mod privacy;
+mod privileges;
+mod registry;
+mod relay;
+mod rwho;
+mod server;
+mod signals;
+mod stdio;
+mod subscriptions;
+mod tcp;
 
-use args::Args;
-use std::net::{SocketAddr, UdpSocket};
+use args::{Args, Command};
+use config::Config;
+use server::{Server, Sockets};
+use std::fs;
+use std::net::{TcpListener, UdpSocket};
 use std::process;
-use std::str::FromStr;
+use std::time::Duration;
 use clap::Parser;
-use whrd::error::{WhereError, WhereResult};
-use whrd::{SessionCollection, WHERED_MAGIC};
+use log::{debug, error, info};
+use logger::Logger;
+use notify::Notifier;
+use whrd::error::WhereResult;
+use whrd::SessionCollection;
 
 fn main() {
     let args = Args::parse();
-    let listen_addr = args.listen_addr.unwrap_or(String::from("0.0.0.0:15"));
+    let config = Config::build(&args);
 
-    if let Err(e) = run_server(&listen_addr) {
-        eprintln!("whered: {}", e);
+    if let Some(Command::Ctl { socket, command }) = &args.command {
+        let path = socket.as_ref().unwrap_or(&config.control.path);
+
+        if let Err(e) = control::run_client(path, *command) {
+            eprintln!("whered: {e}");
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Some(Command::Record) = &args.command {
+        println!("{}", fixture::record(SessionCollection::fetch()));
+        return;
+    }
+
+    if let Err(e) = Logger::init(&config.log) {
+        eprintln!("whered: Failed to set up logging: {e}");
+        process::exit(1);
+    }
+
+    let res = if args.stdio {
+        stdio::serve(config)
+    } else {
+        run_server(&args, config)
+    };
+
+    if let Err(e) = res {
+        error!("{}", e);
         process::exit(1);
     }
 }
 
-fn run_server(listen_addr: &str) -> WhereResult<()> {
-    let socket_addr_result = SocketAddr::from_str(listen_addr);
+fn run_server(args: &Args, config: Config) -> WhereResult<()> {
+    let notifier = Notifier::from_env()?;
 
-    match socket_addr_result {
-        Ok(socket_addr) => {
-            let socket = UdpSocket::bind(socket_addr)?;
-            println!("Now listening on {} port {}/udp", socket_addr.ip(), socket_addr.port());
+    if config.metrics.enabled {
+        let listener = TcpListener::bind(&config.metrics.address)?;
+        let socket_addr = listener.local_addr()?;
+        info!("Serving metrics on {} port {}/tcp", socket_addr.ip(), socket_addr.port());
 
-            loop {
-                if let Err(e) = handle_request(&socket) {
-                    eprintln!("whered: {}", e);
-                }
-            }
+        metrics::start_endpoint(listener, config.server.max_connections);
+    }
+
+    let control = if config.control.enabled {
+        let listener = control::bind(&config.control)?;
+        info!("Accepting admin commands on {}", config.control.path.display());
+
+        Some(listener)
+    } else {
+        None
+    };
+    let control_path = config.control.path.clone();
+
+    let local = if config.local.enabled {
+        let listener = local::bind(&config.local)?;
+        info!("Now listening on {}", config.local.path.display());
+
+        Some(listener)
+    } else {
+        None
+    };
+    let local_path = config.local.path.clone();
+
+    let registration = if config.registration.enabled {
+        let listener = registry::bind(&config.registration)?;
+        info!("Accepting session registrations on {}", config.registration.path.display());
+
+        Some(listener)
+    } else {
+        None
+    };
+    let registration_path = config.registration.path.clone();
+
+    let finger = if config.finger.enabled {
+        let listener = TcpListener::bind(&config.finger.address)?;
+        let socket_addr = listener.local_addr()?;
+        info!("Serving finger on {} port {}/tcp", socket_addr.ip(), socket_addr.port());
+
+        Some(listener)
+    } else {
+        None
+    };
+
+    let tcp = if config.tcp.enabled {
+        let listener = TcpListener::bind(&config.tcp.address)?;
+        let socket_addr = listener.local_addr()?;
+        info!("Now listening on {} port {}/tcp", socket_addr.ip(), socket_addr.port());
+
+        Some(listener)
+    } else {
+        None
+    };
+
+    let rwho = if config.rwho.enabled {
+        // rwhod ignores status packets that don't come from its own port
+        let socket = UdpSocket::bind(&config.rwho.address)?;
+        socket.set_broadcast(true)?;
+
+        let socket_addr = socket.local_addr()?;
+        info!("Sending rwho status from {} port {}/udp", socket_addr.ip(), socket_addr.port());
+
+        Some(socket)
+    } else {
+        None
+    };
+
+    let inherited = if args.inetd {
+        vec![activation::inetd_socket()?]
+    } else {
+        activation::listen_fds()?
+    };
+
+    let (udp, idle_timeout) = if !inherited.is_empty() {
+        for socket in &inherited {
+            let socket_addr = socket.local_addr()?;
+            info!("Now listening on {} port {}/udp (inherited)", socket_addr.ip(), socket_addr.port());
         }
-        Err(e) => {
-            eprintln!("whered: {}", WhereError::from(e));
-            process::exit(1);
+
+        let idle_timeout = Some(config.server.idle_timeout)
+            .filter(|timeout| *timeout > 0)
+            .map(Duration::from_secs);
+
+        (inherited, idle_timeout)
+    } else {
+        let mut sockets = vec![];
+
+        for listener in &config.listen {
+            let socket = listener.bind()?;
+            let socket_addr = socket.local_addr()?;
+
+            match &listener.interface {
+                Some(interface) => info!("Now listening on {} port {}/udp via {interface}", socket_addr.ip(), socket_addr.port()),
+                None => info!("Now listening on {} port {}/udp", socket_addr.ip(), socket_addr.port())
+            }
+
+            sockets.push(socket);
         }
+
+        (sockets, None)
+    };
+
+    // Finding the paths can mean going through every user, so only Landlock pays for it
+    let (readable, writable) = if config.privileges.landlock {
+        (config.readable_paths(), config.writable_paths())
+    } else {
+        (vec![], vec![])
+    };
+    config.privileges.apply(&readable, &writable, args.allow_root)?;
+
+    let mut socket_paths = vec![];
+
+    if control.is_some() {
+        socket_paths.push(control_path);
     }
-}
 
-fn handle_request(socket: &UdpSocket) -> WhereResult<()> {
-    let mut buf = [0; WHERED_MAGIC.len()];
+    if local.is_some() {
+        socket_paths.push(local_path);
+    }
 
-    let (_, src) = socket.recv_from(&mut buf)?;
-    println!("{src}: New client!");
+    if registration.is_some() {
+        socket_paths.push(registration_path);
+    }
 
-    let sessions = SessionCollection::fetch();
-    let buf = sessions.to_udp_payload()?;
+    let sockets = Sockets {
+        udp,
+        control,
+        finger,
+        rwho,
+        tcp,
+        local,
+        registration
+    };
+    let res = Server::run(sockets, config, args, idle_timeout, notifier);
 
-    socket.send_to(&buf, src)?;
-    println!("{src}: Completed request within {} bytes", buf.len());
+    // This may not be allowed anymore after dropping privileges, in which case the sockets
+    // are replaced on the next start instead
+    for path in socket_paths {
+        if let Err(e) = fs::remove_file(&path) {
+            debug!("Failed to remove {}: {e}", path.display());
+        }
+    }
 
-    Ok(())
+    res
 }
@@ -0,0 +1,163 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use log::debug;
+use crate::pool::ConnectionLimit;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub static METRICS: Metrics = Metrics::new();
+pub static HEALTH: Health = Health::new();
+
+pub struct Metrics {
+    pub requests_served: AtomicU64,
+    pub requests_rejected: AtomicU64,
+    pub requests_rate_limited: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub encode_errors: AtomicU64,
+    pub responses_truncated: AtomicU64,
+    pub sessions: AtomicU64,
+    pub utmp_reads: AtomicU64,
+    pub utmp_read_micros: AtomicU64
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            requests_served: AtomicU64::new(0),
+            requests_rejected: AtomicU64::new(0),
+            requests_rate_limited: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            encode_errors: AtomicU64::new(0),
+            responses_truncated: AtomicU64::new(0),
+            sessions: AtomicU64::new(0),
+            utmp_reads: AtomicU64::new(0),
+            utmp_read_micros: AtomicU64::new(0)
+        }
+    }
+
+    pub fn increment(counter: &AtomicU64) {
+        Self::add(counter, 1);
+    }
+
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn set(gauge: &AtomicU64, value: u64) {
+        gauge.store(value, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let metrics = [
+            ("whered_requests_served_total", "counter", "Requests answered successfully.", self.requests_served.load(Ordering::Relaxed) as f64),
+            ("whered_requests_rejected_total", "counter", "Requests ignored because they were malformed.", self.requests_rejected.load(Ordering::Relaxed) as f64),
+            ("whered_requests_rate_limited_total", "counter", "Requests dropped because every worker was busy and the queue was full.", self.requests_rate_limited.load(Ordering::Relaxed) as f64),
+            ("whered_sent_bytes_total", "counter", "Bytes sent to clients, including notifications.", self.bytes_sent.load(Ordering::Relaxed) as f64),
+            ("whered_encode_errors_total", "counter", "Responses that could not be encoded.", self.encode_errors.load(Ordering::Relaxed) as f64),
+            ("whered_responses_truncated_total", "counter", "Responses too large for UDP, which clients were told to ask for over TCP.", self.responses_truncated.load(Ordering::Relaxed) as f64),
+            ("whered_sessions", "gauge", "Sessions found during the last read of utmp.", self.sessions.load(Ordering::Relaxed) as f64),
+            ("whered_utmp_reads_total", "counter", "Reads of utmp.", self.utmp_reads.load(Ordering::Relaxed) as f64),
+            ("whered_utmp_read_duration_seconds", "gauge", "Time taken by the last read of utmp.", self.utmp_read_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0)
+        ];
+
+        metrics.iter()
+            .map(|(name, kind, help, value)| format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"))
+            .collect()
+    }
+}
+
+/// Whether whered is in a state to answer requests, as reported at /healthz.  Each flag is
+/// set by whatever notices the problem, and cleared once it is over.
+pub struct Health {
+    pub shutting_down: AtomicBool,
+    pub stalled: AtomicBool,
+    pub read_failed: AtomicBool,
+    pub encode_failed: AtomicBool
+}
+
+impl Health {
+    const fn new() -> Self {
+        Self {
+            shutting_down: AtomicBool::new(false),
+            stalled: AtomicBool::new(false),
+            read_failed: AtomicBool::new(false),
+            encode_failed: AtomicBool::new(false)
+        }
+    }
+
+    /// Sets a flag, returning whether it changed.
+    pub fn set(flag: &AtomicBool, value: bool) -> bool {
+        flag.swap(value, Ordering::Relaxed) != value
+    }
+
+    /// Lists what is wrong, if anything.
+    pub fn problems(&self) -> Vec<&'static str> {
+        [
+            (&self.shutting_down, "shutting down"),
+            (&self.stalled, "a thread stopped making progress"),
+            (&self.read_failed, "the last read of the sessions failed"),
+            (&self.encode_failed, "the last response could not be encoded")
+        ].into_iter()
+            .filter(|(flag, _)| flag.load(Ordering::Relaxed))
+            .map(|(_, problem)| problem)
+            .collect()
+    }
+}
+
+pub fn start_endpoint(listener: TcpListener, max_connections: usize) {
+    let limit = ConnectionLimit::new(max_connections);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Metrics endpoint: {e}");
+                    continue;
+                }
+            };
+
+            // Scrapers retry later, so there is nothing more to do about the ones over the limit
+            let _ = limit.spawn(stream, |stream| {
+                if let Err(e) = handle_connection(stream) {
+                    debug!("Metrics endpoint: {e}");
+                }
+            });
+        }
+    });
+}
+
+fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(&stream);
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, nothing in them matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", METRICS.render()),
+        ("GET", "/healthz") => match HEALTH.problems().as_slice() {
+            [] => ("200 OK", "text/plain", String::from("ok\n")),
+            problems => ("503 Service Unavailable", "text/plain", problems.iter().map(|problem| format!("{problem}\n")).collect())
+        },
+        ("GET", _) => ("404 Not Found", "text/plain", String::from("Not found\n")),
+        _ => ("405 Method Not Allowed", "text/plain", String::from("Method not allowed\n"))
+    };
+
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+}
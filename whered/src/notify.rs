@@ -0,0 +1,62 @@
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+use log::debug;
+
+/// Reports the state of whered to systemd through the sd_notify protocol.
+pub struct Notifier {
+    socket: UnixDatagram,
+    watchdog: Option<Duration>
+}
+
+impl Notifier {
+    /// Connects to the socket given by systemd, if any.  This has to be done before dropping
+    /// privileges, as the socket might not be reachable anymore afterwards.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+
+        let socket = UnixDatagram::unbound()?;
+        let path = path.to_string_lossy();
+
+        match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?;
+            },
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract notification sockets are only available on Linux")),
+            None => socket.connect(&*path)?
+        }
+
+        // The watchdog is only meant for us if systemd says so, not for a parent process
+        let for_us = env::var("WATCHDOG_PID").ok()
+            .is_none_or(|pid| pid.parse::<u32>().ok() == Some(std::process::id()));
+        let watchdog = env::var("WATCHDOG_USEC").ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && for_us)
+            .map(Duration::from_micros);
+
+        Ok(Some(Self {
+            socket,
+            watchdog
+        }))
+    }
+
+    /// Sends newline-separated variable assignments, such as "READY=1".
+    pub fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send(state.as_bytes()) {
+            debug!("Failed to notify systemd: {e}");
+        }
+    }
+
+    /// How often systemd expects to hear from us before considering whered stuck.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use log::warn;
+use whrd::{Session, SessionCollection};
+use crate::config::{OptOutAction, OptOutConfig};
+use crate::passwd;
+
+const ANONYMOUS_USER: &str = "anonymous";
+
+impl OptOutConfig {
+    /// Omits or anonymizes the sessions of users who opted out, either with a marker file in
+    /// their home directory or by being listed by the admin.
+    pub fn apply(&self, sessions: SessionCollection) -> SessionCollection {
+        if !self.enabled {
+            return sessions;
+        }
+
+        let listed = self.read_list();
+        let mut opted_out = HashMap::new();
+
+        let sessions = sessions.into_vec().into_iter()
+            .filter_map(|session| {
+                let is_opted_out = *opted_out.entry(session.user.clone())
+                    .or_insert_with(|| listed.as_ref().is_none_or(|listed| listed.contains(&session.user)) || self.has_marker(&session.user));
+
+                match (is_opted_out, self.action) {
+                    (false, _) => Some(session),
+                    (true, OptOutAction::Omit) => None,
+                    (true, OptOutAction::Anonymize) => Some(Session {
+                        user: ANONYMOUS_USER.to_string(),
+                        remote: None,
+                        real_name: None,
+                        status: None,
+                        ..session
+                    })
+                }
+            })
+            .collect();
+
+        SessionCollection::from_vec(sessions)
+    }
+
+    /// Reads the users the admin listed, or nothing if the list can't be read, in which case
+    /// every user has to be treated as opted out.
+    fn read_list(&self) -> Option<HashSet<String>> {
+        let Some(path) = &self.list else {
+            return Some(HashSet::new());
+        };
+
+        match fs::read_to_string(path) {
+            Ok(list) => Some(list.lines()
+                .map(|line| line.split('#').next().unwrap().trim())
+                .filter(|user| !user.is_empty())
+                .map(str::to_string)
+                .collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Some(HashSet::new()),
+            Err(e) => {
+                warn!("Failed to read {}, treating every user as opted out: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    fn has_marker(&self, user: &str) -> bool {
+        if self.marker.is_empty() {
+            return false;
+        }
+
+        let Some(entry) = passwd::lookup(user) else {
+            return false;
+        };
+        let path = entry.home.join(&self.marker);
+
+        // Only the marker's presence matters, so symlinks aren't followed.  A home directory
+        // whered isn't allowed into may well have one, so the user is hidden to be safe.
+        match fs::symlink_metadata(&path) {
+            Ok(_) => true,
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => {
+                warn!("Unable to look for {}, treating {user} as opted out: {e}", path.display());
+                true
+            }
+        }
+    }
+}
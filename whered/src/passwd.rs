@@ -0,0 +1,61 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+pub struct PasswdEntry {
+    pub uid: u32,
+    pub gecos: String,
+    pub home: PathBuf
+}
+
+/// Looks a user up in the password database.  Workers do this concurrently, so the
+/// reentrant variant of getpwnam is needed.
+pub fn lookup(name: &str) -> Option<PasswdEntry> {
+    let name_c = CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+
+    let res = unsafe { libc::getpwnam_r(name_c.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+
+    if res != 0 || result.is_null() {
+        return None;
+    }
+
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    let gecos = unsafe { CStr::from_ptr(passwd.pw_gecos) };
+
+    Some(PasswdEntry {
+        uid: passwd.pw_uid,
+        gecos: gecos.to_string_lossy().into_owned(),
+        home: PathBuf::from(OsStr::from_bytes(home.to_bytes()))
+    })
+}
+
+/// Lists the home directories of every user in the password database.  This walks the whole
+/// database with getpwent, so it must only be called before workers start.
+pub fn home_directories() -> Vec<PathBuf> {
+    let mut homes = vec![];
+
+    unsafe {
+        libc::setpwent();
+
+        loop {
+            let entry = libc::getpwent();
+
+            if entry.is_null() {
+                break;
+            }
+
+            let home = PathBuf::from(OsStr::from_bytes(CStr::from_ptr((*entry).pw_dir).to_bytes()));
+
+            if !homes.contains(&home) {
+                homes.push(home);
+            }
+        }
+
+        libc::endpwent();
+    }
+
+    homes
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A fixed set of threads processing jobs from a bounded queue.
+pub struct WorkerPool<T> {
+    sender: SyncSender<T>,
+    workers: Vec<JoinHandle<()>>
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub fn new<F>(workers: usize, queue_length: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel(queue_length);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                let handler = handler.clone();
+
+                thread::spawn(move || Self::run_worker(&receiver, &*handler))
+            })
+            .collect();
+
+        Self {
+            sender,
+            workers
+        }
+    }
+
+    fn run_worker(receiver: &Mutex<Receiver<T>>, handler: &(dyn Fn(T) + Send + Sync)) {
+        loop {
+            // Only hold the lock while waiting, so that other workers can pick up the next job
+            let job = match receiver.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return
+            };
+
+            handler(job);
+        }
+    }
+
+    /// Waits for every queued job to be processed, then stops the workers.
+    pub fn shutdown(self) {
+        drop(self.sender);
+
+        for worker in self.workers {
+            worker.join().unwrap();
+        }
+    }
+
+    /// Queues a job, or gives it back if all workers are busy and the queue is full.
+    pub fn submit(&self, job: T) -> Result<(), T> {
+        self.sender.try_send(job).map_err(|e| match e {
+            TrySendError::Full(job) | TrySendError::Disconnected(job) => job
+        })
+    }
+}
+
+/// Handles every connection on its own thread, so that a slow client only holds up itself,
+/// as long as there aren't too many of them at once.
+pub struct ConnectionLimit {
+    active: Arc<AtomicUsize>,
+    max: usize
+}
+
+// Makes room for another connection when its thread ends, even if it panicked
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            max: max.max(1)
+        }
+    }
+
+    /// Handles a connection on a new thread, or gives it back if the limit is reached.
+    pub fn spawn<T, F>(&self, connection: T, handler: F) -> Result<(), T>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static
+    {
+        let reserved = self.active.fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+            Some(active + 1).filter(|active| *active <= self.max)
+        });
+
+        if reserved.is_err() {
+            return Err(connection);
+        }
+
+        let guard = ConnectionGuard(self.active.clone());
+
+        thread::spawn(move || {
+            let _guard = guard;
+            handler(connection);
+        });
+
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
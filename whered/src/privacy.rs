@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use whrd::{Session, SessionCollection};
+use whrd::subscription::SessionEvent;
+use crate::config::{Network, PrivacyConfig, RemoteRedaction};
+use crate::passwd;
+
+impl Network {
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+/// Finds the rule set that applies to a client: the first one listing a network it belongs
+/// to, or listing no network at all.  Clients matching no rule set see every session.
+pub fn rule_for(rules: &[PrivacyConfig], client: IpAddr) -> Option<usize> {
+    rules.iter().position(|rule| rule.networks.is_empty() || rule.networks.iter().any(|network| network.contains(client)))
+}
+
+impl PrivacyConfig {
+    /// Removes the excluded sessions and redacts the remaining ones.
+    pub fn apply(&self, sessions: &SessionCollection) -> SessionCollection {
+        let mut uids = HashMap::new();
+
+        let sessions = sessions.clone().into_vec().into_iter()
+            .filter_map(|session| self.apply_session(session, &mut uids))
+            .collect();
+
+        SessionCollection::from_vec(sessions)
+    }
+
+    pub fn apply_events(&self, events: &[SessionEvent]) -> Vec<SessionEvent> {
+        let mut uids = HashMap::new();
+
+        events.iter()
+            .filter_map(|event| Some(SessionEvent {
+                kind: event.kind,
+                session: self.apply_session(event.session.clone(), &mut uids)?
+            }))
+            .collect()
+    }
+
+    fn apply_session(&self, session: Session, uids: &mut HashMap<String, Option<u32>>) -> Option<Session> {
+        if self.exclude_users.iter().any(|pattern| matches_pattern(pattern, &session.user))
+            || self.exclude_ttys.iter().any(|pattern| matches_pattern(pattern, &session.tty)) {
+            return None;
+        }
+
+        if !self.exclude_uids.is_empty() {
+            let uid = *uids.entry(session.user.clone())
+                .or_insert_with(|| passwd::lookup(&session.user).map(|entry| entry.uid));
+
+            // Users that can't be looked up, for example in a chroot without /etc/passwd,
+            // can't be excluded by UID
+            if uid.is_some_and(|uid| self.exclude_uids.iter().any(|[min, max]| (*min..=*max).contains(&uid))) {
+                return None;
+            }
+        }
+
+        let remote = session.remote.as_deref().and_then(|remote| self.redact_remote(remote));
+
+        Some(Session {
+            remote,
+            ..session
+        })
+    }
+
+    fn redact_remote(&self, remote: &str) -> Option<String> {
+        match self.remote {
+            RemoteRedaction::Keep => Some(remote.to_string()),
+            RemoteRedaction::Drop => None,
+            // Remotes that aren't addresses, such as host names, can't be shortened
+            RemoteRedaction::Prefix => match IpAddr::from_str(remote).ok()? {
+                IpAddr::V4(address) => {
+                    let prefix = self.ipv4_prefix.min(32);
+                    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                    Some(format!("{}/{prefix}", std::net::Ipv4Addr::from(u32::from(address) & mask)))
+                },
+                IpAddr::V6(address) => {
+                    let prefix = self.ipv6_prefix.min(128);
+                    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                    Some(format!("{}/{prefix}", std::net::Ipv6Addr::from(u128::from(address) & mask)))
+                }
+            },
+            // Keyed, so that clients can't hash every address themselves, and stable across
+            // restarts and upgrades
+            RemoteRedaction::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(self.hash_key.as_bytes()).unwrap();
+                mac.update(remote.as_bytes());
+
+                let hash = mac.finalize().into_bytes();
+                Some(hash[..8].iter().map(|byte| format!("{byte:02x}")).collect())
+            }
+        }
+    }
+}
+
+/// Matches text against a pattern where "*" stands for any number of characters and "?" for
+/// exactly one.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Where to resume from if the last "*" has to swallow one more character
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,92 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use whrd::error::WhereResult;
+use crate::config::PrivilegeConfig;
+
+impl PrivilegeConfig {
+    /// Gives up root privileges as configured, once all the sockets have been bound.
+    pub fn apply(&self, readable: &[PathBuf], writable: &[PathBuf], allow_root: bool) -> WhereResult<()> {
+        // Look everything up before a chroot hides /etc
+        let user = self.user.as_deref().map(lookup_user).transpose()?;
+        let gid = match &self.group {
+            Some(group) => Some(lookup_group(group)?),
+            None => user.map(|(_, gid)| gid)
+        };
+
+        if let Some(path) = &self.chroot {
+            change_root(path)?;
+        }
+
+        // Without root, there are no privileges to drop in the first place
+        if unsafe { libc::geteuid() } == 0 {
+            if let Some(gid) = gid {
+                check(unsafe { libc::setgroups(1, &gid) })?;
+                check(unsafe { libc::setgid(gid) })?;
+            }
+
+            if let Some((uid, _)) = user {
+                check(unsafe { libc::setuid(uid) })?;
+            }
+        }
+
+        if self.landlock {
+            restrict_filesystem(readable, writable)?;
+        }
+
+        if unsafe { libc::geteuid() } == 0 && !self.allow_root && !allow_root {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Refusing to run as root, set privileges.user in the configuration file or pass --allow-root").into());
+        }
+
+        Ok(())
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let name_c = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // No other thread is running yet, so the static buffer of getpwnam is safe to use
+    let passwd = unsafe { libc::getpwnam(name_c.as_ptr()) };
+
+    if passwd.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No such user: {name}")));
+    }
+
+    Ok(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) })
+}
+
+pub fn lookup_group(name: &str) -> io::Result<libc::gid_t> {
+    let name_c = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let group = unsafe { libc::getgrnam(name_c.as_ptr()) };
+
+    if group.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No such group: {name}")));
+    }
+
+    Ok(unsafe { (*group).gr_gid })
+}
+
+fn change_root(path: &Path) -> io::Result<()> {
+    let path_c = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    check(unsafe { libc::chroot(path_c.as_ptr()) })?;
+    std::env::set_current_dir("/")
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_filesystem(readable: &[PathBuf], writable: &[PathBuf]) -> io::Result<()> {
+    crate::landlock::restrict(readable, writable)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restrict_filesystem(_readable: &[PathBuf], _writable: &[PathBuf]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Landlock is only available on Linux"))
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs as unix_fs;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use whrd::{Session, MAX_ORIGIN_LENGTH, MAX_REMOTE_LENGTH, MAX_USER_TTY_LENGTH};
+use crate::config::RegistrationConfig;
+use crate::control;
+use crate::metrics::{Metrics, METRICS};
+use crate::pool::ConnectionLimit;
+use crate::privileges;
+use crate::server::Server;
+
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+// Longer lines are not something a registration request needs
+const MAX_LINE_LENGTH: u64 = 4096;
+const ERROR_PREFIX: &str = "error: ";
+
+/// Sessions that applications such as web terminals registered, as they never reach utmp.
+#[derive(Default)]
+pub struct Registry {
+    sessions: HashMap<String, Registered>,
+    changed: bool
+}
+
+struct Registered {
+    session: Session,
+    expires_at: Instant
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    Register {
+        id: String,
+        user: String,
+        tty: String,
+        origin: String,
+        remote: Option<String>,
+        pid: Option<i32>,
+        ttl: Option<u64>
+    },
+    Refresh {
+        id: String,
+        ttl: Option<u64>
+    },
+    Unregister {
+        id: String
+    },
+    List
+}
+
+/// A registered session as listed to other whered processes, such as those started with
+/// --stdio, which don't share the registry of the daemon.
+#[derive(Serialize, Deserialize)]
+struct Listed {
+    id: String,
+    user: String,
+    tty: String,
+    origin: Option<String>,
+    remote: Option<String>,
+    pid: i32,
+    login_time: i64,
+    ttl: u64
+}
+
+impl Registry {
+    /// Adds a session, or replaces the one with the same ID while keeping its login time.
+    pub fn register(&mut self, id: String, mut session: Session, ttl: Duration) {
+        if let Some(registered) = self.sessions.get(&id) {
+            session.login_time = registered.session.login_time;
+        }
+
+        self.sessions.insert(id, Registered {
+            session,
+            expires_at: Instant::now() + ttl
+        });
+        self.changed = true;
+    }
+
+    /// Pushes back when a session expires.  Returns whether there is one with this ID.
+    pub fn refresh(&mut self, id: &str, ttl: Duration) -> bool {
+        match self.sessions.get_mut(id) {
+            Some(registered) => {
+                registered.expires_at = Instant::now() + ttl;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn unregister(&mut self, id: &str) -> bool {
+        let removed = self.sessions.remove(id).is_some();
+        self.changed |= removed;
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.sessions.contains_key(id)
+    }
+
+    /// Whether the sessions changed or some expired since they were last listed.
+    pub fn is_outdated(&self) -> bool {
+        let now = Instant::now();
+        self.changed || self.sessions.values().any(|registered| registered.expires_at <= now)
+    }
+
+    /// Lists the sessions that haven't expired, forgetting about the others.
+    pub fn sessions(&mut self) -> Vec<Session> {
+        let now = Instant::now();
+        self.sessions.retain(|_, registered| registered.expires_at > now);
+        self.changed = false;
+
+        self.sessions.values().map(|registered| registered.session.clone()).collect()
+    }
+
+    fn list(&self) -> Vec<Listed> {
+        let now = Instant::now();
+
+        self.sessions.iter()
+            .filter(|(_, registered)| registered.expires_at > now)
+            .map(|(id, registered)| Listed {
+                id: id.clone(),
+                user: registered.session.user.clone(),
+                tty: registered.session.tty.clone(),
+                origin: registered.session.origin.clone(),
+                remote: registered.session.remote.clone(),
+                pid: registered.session.pid,
+                login_time: registered.session.login_time,
+                // Rounded up, so that a session about to expire isn't listed as expired
+                ttl: (registered.expires_at - now).as_secs() + 1
+            })
+            .collect()
+    }
+}
+
+/// Replaces the sessions of another registry with those the daemon knows about, by asking
+/// for them on the registration socket.  Returns how many there are.
+pub fn copy_from_daemon(config: &RegistrationConfig, registry: &mut Registry) -> io::Result<usize> {
+    let mut stream = UnixStream::connect(&config.path)?;
+    stream.set_read_timeout(Some(REGISTRATION_TIMEOUT))?;
+    stream.set_write_timeout(Some(REGISTRATION_TIMEOUT))?;
+    writeln!(stream, r#"{{"action": "list"}}"#)?;
+
+    let mut copy = Registry::default();
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        if line == "ok" {
+            *registry = copy;
+            // Sessions the daemon forgot about have to disappear too
+            registry.changed = true;
+            return Ok(registry.len());
+        }
+
+        if let Some(e) = line.strip_prefix(ERROR_PREFIX) {
+            return Err(io::Error::other(e.to_string()));
+        }
+
+        let listed: Listed = serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let session = Session {
+            host: None,
+            pid: listed.pid,
+            login_time: listed.login_time,
+            user: listed.user,
+            tty: listed.tty,
+            remote: listed.remote,
+            active: true,
+            real_name: None,
+            status: None,
+            origin: listed.origin
+        };
+
+        copy.register(listed.id, session, Duration::from_secs(listed.ttl));
+    }
+
+    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The list of registered sessions was cut short"))
+}
+
+/// Creates the registration socket.  Its group and permissions decide which applications
+/// can register sessions.
+pub fn bind(config: &RegistrationConfig) -> io::Result<UnixListener> {
+    let listener = control::bind_socket(&config.path, config.mode)?;
+
+    if let Some(group) = &config.group {
+        unix_fs::chown(&config.path, None, Some(privileges::lookup_group(group)?))?;
+    }
+
+    Ok(listener)
+}
+
+/// Accepts registration requests, one JSON object per line and per connection.
+pub fn start_listener(listener: UnixListener, server: Arc<Server>, config: RegistrationConfig, max_connections: usize) {
+    let config = Arc::new(config);
+    let limit = ConnectionLimit::new(max_connections);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Registration socket: {e}");
+                    continue;
+                }
+            };
+
+            let res = limit.spawn(stream, {
+                let server = server.clone();
+                let config = config.clone();
+
+                move |stream| {
+                    if let Err(e) = handle_connection(stream, &server, &config) {
+                        debug!("Registration socket: {e}");
+                    }
+                }
+            });
+
+            if res.is_err() {
+                Metrics::increment(&METRICS.requests_rate_limited);
+                debug!("Registration socket: Closed connection, too many are open");
+            }
+        }
+    });
+}
+
+fn handle_connection(mut stream: UnixStream, server: &Server, config: &RegistrationConfig) -> io::Result<()> {
+    stream.set_read_timeout(Some(REGISTRATION_TIMEOUT))?;
+    stream.set_write_timeout(Some(REGISTRATION_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).take(MAX_LINE_LENGTH).read_line(&mut line)?;
+
+    let res = serde_json::from_str(&line)
+        .map_err(|e| format!("Invalid request: {e}"))
+        .and_then(|request| handle_request(request, server, config));
+
+    match res {
+        Ok(listed) => writeln!(stream, "{listed}ok"),
+        Err(e) => writeln!(stream, "{ERROR_PREFIX}{e}")
+    }
+}
+
+/// Carries out a request.  Returns the lines to send before "ok", which only listing has.
+fn handle_request(request: Request, server: &Server, config: &RegistrationConfig) -> Result<String, String> {
+    let mut cache = server.cache().lock();
+    let registry = cache.registry();
+
+    match request {
+        Request::Register { id, user, tty, origin, remote, pid, ttl } => {
+            check_field("user", &user, MAX_USER_TTY_LENGTH)?;
+            check_field("tty", &tty, MAX_USER_TTY_LENGTH)?;
+            check_field("origin", &origin, MAX_ORIGIN_LENGTH)?;
+
+            if let Some(remote) = &remote {
+                check_field("remote", remote, MAX_REMOTE_LENGTH)?;
+            }
+
+            if !registry.contains(&id) && registry.len() >= config.max_sessions {
+                return Err(format!("Too many registered sessions, the limit is {}", config.max_sessions));
+            }
+
+            info!("Registered session {id:?} of {user} on {tty} from {origin}");
+
+            let session = Session {
+                host: None,
+                pid: pid.unwrap_or(0),
+                login_time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64),
+                user,
+                tty,
+                remote,
+                active: true,
+                real_name: None,
+                status: None,
+                origin: Some(origin)
+            };
+
+            registry.register(id, session, config.ttl(ttl));
+        },
+        Request::Refresh { id, ttl } => {
+            if !registry.refresh(&id, config.ttl(ttl)) {
+                return Err(format!("No session registered as {id:?}"));
+            }
+
+            debug!("Refreshed session {id:?}");
+        },
+        Request::Unregister { id } => {
+            if !registry.unregister(&id) {
+                return Err(format!("No session registered as {id:?}"));
+            }
+
+            info!("Unregistered session {id:?}");
+        },
+        Request::List => {
+            return Ok(registry.list().iter()
+                .map(|listed| serde_json::to_string(listed).unwrap() + "\n")
+                .collect());
+        }
+    }
+
+    Ok(String::new())
+}
+
+/// Makes sure a field fits in a WHRD entry and can't break the output of clients.
+fn check_field(name: &str, value: &str, max_length: usize) -> Result<(), String> {
+    if value.is_empty() || value.len() > max_length {
+        return Err(format!("{name} must be between 1 and {max_length} bytes long"));
+    }
+
+    if value.chars().any(char::is_control) {
+        return Err(format!("{name} must not contain control characters"));
+    }
+
+    Ok(())
+}
+
+impl RegistrationConfig {
+    /// How long a session lives without a refresh, as requested but within the limit.
+    fn ttl(&self, requested: Option<u64>) -> Duration {
+        Duration::from_secs(requested.unwrap_or(self.default_ttl).min(self.max_ttl).max(1))
+    }
+}
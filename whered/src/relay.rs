@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use where_rs::config::{GlobalConfig, Server};
+use whrd::error::WhereResult;
+use whrd::{Session, MAX_HOST_LENGTH};
+use crate::config::RelayConfig;
+
+const FIRST_ANSWERS_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The sessions of downstream servers, which whered lists along with its own, each under
+/// the label of the server it comes from.
+pub struct Relay {
+    answers: Mutex<HashMap<String, Answer>>,
+    max_age: Duration,
+    stopped: AtomicBool,
+    // Servers that weren't queried yet
+    pending: AtomicUsize
+}
+
+#[derive(Clone)]
+struct Answer {
+    sessions: Vec<Session>,
+    fetched_at: Instant
+}
+
+impl Relay {
+    /// Starts querying every downstream server in the background, each at its own pace so
+    /// that a slow one doesn't hold up the others.  The last answers of a previous relay are
+    /// kept for the servers that are still relayed, until they answer again.
+    pub fn start(config: RelayConfig, previous: Option<&Relay>) -> Arc<Self> {
+        let answers = previous.map(|previous| previous.answers.lock().unwrap().clone()).unwrap_or_default()
+            .into_iter()
+            .filter(|(label, _)| config.server.iter().any(|server| server.get_label() == *label))
+            .collect();
+
+        let relay = Arc::new(Self {
+            answers: Mutex::new(answers),
+            max_age: Duration::from_secs(config.max_age),
+            stopped: AtomicBool::new(false),
+            pending: AtomicUsize::new(config.server.len())
+        });
+        let interval = Duration::from_secs(config.interval);
+
+        for server in config.server {
+            let relay = relay.clone();
+            let global = config.global.clone();
+
+            thread::spawn(move || {
+                let label = server.get_label();
+                let mut failing = false;
+                let mut first = true;
+
+                while !relay.stopped.load(Ordering::Relaxed) {
+                    let res = relay.refresh(&server, &global);
+
+                    if std::mem::take(&mut first) {
+                        relay.pending.fetch_sub(1, Ordering::Relaxed);
+                    }
+
+                    match res {
+                        Ok(count) => {
+                            if failing {
+                                info!("Relaying the sessions of {label} again");
+                            }
+
+                            debug!("Relaying {count} sessions from {label}");
+                            failing = false;
+                        },
+                        // A server that is down would otherwise fill the log
+                        Err(e) if failing => debug!("Unable to relay the sessions of {label}: {e}"),
+                        Err(e) => {
+                            warn!("Unable to relay the sessions of {label}: {e}");
+                            failing = true;
+                        }
+                    }
+
+                    thread::sleep(interval);
+                }
+            });
+        }
+
+        relay
+    }
+
+    /// Waits until every downstream server was queried once, whether it answered or not.
+    pub fn wait_for_first_answers(&self) {
+        while self.pending.load(Ordering::Relaxed) > 0 {
+            thread::sleep(FIRST_ANSWERS_CHECK_INTERVAL);
+        }
+    }
+
+    /// Makes the threads querying downstream servers exit, once they are done waiting.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Queries a downstream server and remembers its sessions.  Returns how many there are.
+    fn refresh(&self, server: &Server, global: &GlobalConfig) -> WhereResult<usize> {
+        let label = server.get_label();
+
+        // Decoding already attributed them to the label, or to "label/host" for those the
+        // downstream server relays itself
+        let sessions: Vec<Session> = server.process(global)?.into_vec().into_iter()
+            .map(|mut session| {
+                session.host.get_or_insert_with(|| label.clone());
+                session
+            })
+            .filter(|session| session.host.as_ref().is_some_and(|host| host.len() <= MAX_HOST_LENGTH))
+            .collect();
+        let count = sessions.len();
+
+        self.answers.lock().unwrap().insert(label, Answer {
+            sessions,
+            fetched_at: Instant::now()
+        });
+
+        Ok(count)
+    }
+
+    /// Lists the sessions of the downstream servers that answered recently enough.
+    pub fn sessions(&self) -> Vec<Session> {
+        self.answers.lock().unwrap().values()
+            .filter(|answer| answer.fetched_at.elapsed() < self.max_age)
+            .flat_map(|answer| answer.sessions.iter().cloned())
+            .collect()
+    }
+
+    /// Whether a downstream server answered, or an answer became too old, after a point in
+    /// time.
+    pub fn changed_since(&self, since: Instant) -> bool {
+        self.answers.lock().unwrap().values().any(|answer| {
+            let expires_at = answer.fetched_at + self.max_age;
+            answer.fetched_at > since || (expires_at > since && expires_at <= Instant::now())
+        })
+    }
+}
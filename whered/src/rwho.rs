@@ -0,0 +1,104 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+use whrd::rwho::Whod;
+use whrd::{Session, SessionCollection};
+use crate::config::RwhoConfig;
+use crate::metrics::{Metrics, METRICS};
+use crate::server::Server;
+
+/// Broadcasts the current sessions in the format of rwhod, so that rwho and ruptime on other
+/// machines see this one.
+pub fn start_broadcaster(socket: UdpSocket, server: Arc<Server>, config: RwhoConfig) {
+    let hostname = config.hostname.clone().unwrap_or_else(hostname);
+
+    thread::spawn(move || loop {
+        for destination in &config.destinations {
+            if let Err(e) = broadcast(&socket, &server, &hostname, destination) {
+                warn!("Failed to send rwho status to {destination}: {e}");
+            }
+        }
+
+        thread::sleep(Duration::from_secs(config.interval));
+    });
+}
+
+fn broadcast(socket: &UdpSocket, server: &Server, hostname: &str, destination: &str) -> std::io::Result<()> {
+    let addresses: Vec<SocketAddr> = destination.to_socket_addrs()?.collect();
+
+    for address in addresses {
+        // Every destination may be subject to different privacy rules, and relayed sessions
+        // are not on this host
+        let sessions = server.sessions_for(address.ip()).into_vec().into_iter()
+            .filter(|session| session.host.is_none())
+            .collect();
+        let sessions = SessionCollection::from_vec(sessions);
+        let now = unix_time();
+
+        let mut whod = Whod::from_sessions(hostname, &sessions, |session| idle_time(session, now));
+        whod.send_time = now;
+        whod.load_average = load_average();
+        whod.boot_time = boot_time(now);
+
+        let sent = socket.send_to(&whod.to_bytes(), address)?;
+        Metrics::add(&METRICS.bytes_sent, sent as u64);
+        debug!("Sent rwho status with {} sessions to {address}", whod.entries.len());
+    }
+
+    Ok(())
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64)
+}
+
+/// How long the terminal of a session has been left alone, in seconds, like rwhod reports it.
+fn idle_time(session: &Session, now: i64) -> i32 {
+    let mut path = PathBuf::from("/dev");
+    path.push(&session.tty);
+
+    std::fs::metadata(path).ok()
+        .and_then(|metadata| metadata.accessed().ok())
+        .and_then(|accessed| accessed.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |accessed| (now - accessed.as_secs() as i64).max(0) as i32)
+}
+
+fn load_average() -> [i32; 3] {
+    let mut load = [0.0f64; 3];
+
+    if unsafe { libc::getloadavg(load.as_mut_ptr(), 3) } != 3 {
+        return [0; 3];
+    }
+
+    load.map(|load| (load * 100.0) as i32)
+}
+
+#[cfg(target_os = "linux")]
+fn boot_time(now: i64) -> i64 {
+    let mut info: libc::sysinfo = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::sysinfo(&mut info) } < 0 {
+        return 0;
+    }
+
+    now - info.uptime as i64
+}
+
+#[cfg(not(target_os = "linux"))]
+fn boot_time(_now: i64) -> i64 {
+    0
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } < 0 {
+        return "localhost".to_string();
+    }
+
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
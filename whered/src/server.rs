@@ -0,0 +1,464 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use whrd::error::{WhereError, WhereResult};
+use whrd::request::Request;
+use whrd::subscription::subscription_ack;
+use whrd::{SessionCollection, MAX_REQUEST_LENGTH};
+use crate::args::Args;
+use crate::audit::{self, AuditLog, AuditRecord, Outcome};
+use crate::cache::{SessionCache, SharedCache};
+use crate::config::Config;
+use crate::control;
+use crate::finger;
+use crate::local;
+use crate::logger::Logger;
+use crate::metrics::{Health, Metrics, HEALTH, METRICS};
+use crate::notify::Notifier;
+use crate::pool::WorkerPool;
+use crate::registry;
+use crate::relay::Relay;
+use crate::rwho;
+use crate::signals;
+use crate::subscriptions::{self, Subscriptions};
+use crate::tcp;
+
+// How often receiving threads stop waiting for requests to check whether to shut down
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+// How long a thread can go without a heartbeat before it is considered stuck, when systemd
+// doesn't ask for a shorter time
+const STALLED_AFTER: Duration = Duration::from_secs(30);
+
+pub struct Server {
+    subscriptions: Mutex<Subscriptions>,
+    cache: SharedCache,
+    audit: Mutex<Option<AuditLog>>,
+    last_activity: Mutex<Instant>,
+    // When a worker last finished a job
+    workers_heartbeat: Heartbeat,
+    shutting_down: AtomicBool,
+    draining: AtomicBool,
+    started_at: Instant,
+    notifier: Option<Notifier>
+}
+
+/// Everything whered listens on, bound before dropping privileges.
+pub struct Sockets {
+    pub udp: Vec<UdpSocket>,
+    pub control: Option<UnixListener>,
+    pub finger: Option<TcpListener>,
+    pub rwho: Option<UdpSocket>,
+    pub tcp: Option<TcpListener>,
+    pub local: Option<UnixListener>,
+    pub registration: Option<UnixListener>
+}
+
+enum Job {
+    Request {
+        socket: Arc<UdpSocket>,
+        src: SocketAddr,
+        request: Request
+    },
+    // Sent by the supervisor, so that idle workers show they can still pick up jobs
+    Heartbeat
+}
+
+/// When a thread last showed that it isn't stuck.
+struct Heartbeat(Mutex<Instant>);
+
+impl Heartbeat {
+    fn new() -> Self {
+        Self(Mutex::new(Instant::now()))
+    }
+
+    fn beat(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+impl Server {
+    /// Sets up everything answering requests needs, whichever way they come in, and starts
+    /// relaying downstream servers if enabled.
+    pub fn new(config: &Config, notifier: Option<Notifier>) -> WhereResult<Arc<Self>> {
+        let server = Arc::new(Self {
+            subscriptions: Mutex::new(Subscriptions::new(config.subscriptions.clone())),
+            cache: SharedCache::new(SessionCache::new(config.cache.clone(), config.privacy.clone(), config.opt_out.clone(), config.details.clone())),
+            audit: Mutex::new(AuditLog::new(config.audit.clone())?),
+            last_activity: Mutex::new(Instant::now()),
+            workers_heartbeat: Heartbeat::new(),
+            shutting_down: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            started_at: Instant::now(),
+            notifier
+        });
+
+        if config.relay.enabled {
+            server.cache.lock().set_relay(Some(Relay::start(config.relay.clone(), None)));
+        }
+
+        Ok(server)
+    }
+
+    pub fn run(sockets: Sockets, config: Config, args: &Args, idle_timeout: Option<Duration>, notifier: Option<Notifier>) -> WhereResult<()> {
+        let server = Self::new(&config, notifier)?;
+
+        signals::start_handler(server.clone(), args.clone())?;
+        subscriptions::start_watcher(server.clone());
+
+        if let Some(control) = sockets.control {
+            control::start_listener(control, server.clone(), args.clone());
+        }
+
+        if let Some(finger) = sockets.finger {
+            finger::start_listener(finger, server.clone(), config.server.max_connections);
+        }
+
+        if let Some(tcp) = sockets.tcp {
+            tcp::start_listener(tcp, server.clone(), config.server.max_connections);
+        }
+
+        if let Some(local) = sockets.local {
+            local::start_listener(local, server.clone(), config.server.max_connections);
+        }
+
+        if let Some(registration) = sockets.registration {
+            registry::start_listener(registration, server.clone(), config.registration, config.server.max_connections);
+        }
+
+        if let Some(rwho) = sockets.rwho {
+            rwho::start_broadcaster(rwho, server.clone(), config.rwho);
+        }
+
+        if let Some(idle_timeout) = idle_timeout {
+            Self::start_idle_timer(server.clone(), idle_timeout);
+        }
+
+        let pool = Arc::new({
+            let server = server.clone();
+
+            WorkerPool::new(config.server.workers, config.server.queue_length, move |job: Job| {
+                if let Job::Request { socket, src, request } = job {
+                    if let Err(e) = server.handle_request(&socket, src, request) {
+                        server.audit(src, Some(request), None, Outcome::Failed);
+                        warn!("{}: {}", src, e);
+                    }
+
+                    *server.last_activity.lock().unwrap() = Instant::now();
+                }
+
+                server.workers_heartbeat.beat();
+            })
+        });
+
+        let mut receivers = vec![];
+        let mut heartbeats = vec![];
+
+        for socket in sockets.udp {
+            socket.set_read_timeout(Some(SHUTDOWN_CHECK_INTERVAL))?;
+
+            let socket = Arc::new(socket);
+            let pool = pool.clone();
+            let server = server.clone();
+            let heartbeat = Arc::new(Heartbeat::new());
+            heartbeats.push(heartbeat.clone());
+
+            receivers.push(thread::spawn(move || {
+                while !server.shutting_down.load(Ordering::Relaxed) {
+                    heartbeat.beat();
+
+                    match server.receive_request(&socket, &pool) {
+                        Err(WhereError::IOError(e)) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                        Err(e) => debug!("{}", e),
+                        Ok(()) => {}
+                    }
+                }
+            }));
+        }
+
+        server.notify("READY=1");
+        server.supervise(&pool, &heartbeats);
+        server.notify("STOPPING=1");
+
+        for receiver in receivers {
+            receiver.join().unwrap();
+        }
+
+        // Every receiving thread is gone, so nothing else can hold on to the pool
+        if let Ok(pool) = Arc::try_unwrap(pool) {
+            pool.shutdown();
+        }
+
+        info!("Shut down");
+        Ok(())
+    }
+
+    /// Keeps systemd updated about what whered is doing until it shuts down, and shuts down
+    /// once draining is over.  Every receiving thread and the workers have to keep making
+    /// progress for whered to be reported healthy and for the watchdog to be pinged.
+    fn supervise(&self, pool: &WorkerPool<Job>, receivers: &[Arc<Heartbeat>]) {
+        let watchdog = self.notifier.as_ref().and_then(|notifier| notifier.watchdog_interval());
+        // Ping the watchdog twice as often as required, so that one late ping isn't fatal
+        let interval = watchdog.map_or(STATUS_INTERVAL, |watchdog| (watchdog / 2).min(STATUS_INTERVAL));
+        let mut last_update: Option<Instant> = None;
+
+        while !self.shutting_down.load(Ordering::Relaxed) {
+            if self.draining.load(Ordering::Relaxed) && self.subscriptions.lock().unwrap().subscribers().is_empty() {
+                info!("Every subscription has expired, shutting down");
+                self.shutdown();
+                break;
+            }
+
+            if last_update.is_none_or(|time| time.elapsed() >= interval) {
+                // Once a thread is stuck, systemd is better off restarting whered
+                let stalled_after = watchdog.unwrap_or(STALLED_AFTER);
+                let stalled = !receivers.iter().map(Arc::as_ref).chain([&self.workers_heartbeat])
+                    .all(|heartbeat| heartbeat.elapsed() < stalled_after);
+
+                if Health::set(&HEALTH.stalled, stalled) {
+                    if stalled {
+                        warn!("A thread stopped making progress");
+                    } else {
+                        info!("Every thread is making progress again");
+                    }
+                }
+
+                if self.notifier.is_some() {
+                    let subscribers = self.subscriptions.lock().unwrap().subscribers().len();
+                    let status = format!("STATUS=Served {} requests, {} sessions, {subscribers} subscribers",
+                                         METRICS.requests_served.load(Ordering::Relaxed),
+                                         METRICS.sessions.load(Ordering::Relaxed));
+
+                    if watchdog.is_some() && !stalled {
+                        self.notify(&format!("WATCHDOG=1\n{status}"));
+                    } else {
+                        self.notify(&status);
+                    }
+                }
+
+                // Busy workers beat after every job, idle ones have to be given one
+                let _ = pool.submit(Job::Heartbeat);
+                last_update = Some(Instant::now());
+            }
+
+            thread::sleep(SHUTDOWN_CHECK_INTERVAL);
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(state);
+        }
+    }
+
+    /// Stops receiving requests, letting the ones already received complete.
+    pub fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        HEALTH.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Refuses new subscriptions and renewals, so that whered shuts down once the current ones
+    /// have expired.  Returns how many subscriptions are left.
+    pub fn drain(&self) -> usize {
+        self.draining.store(true, Ordering::Relaxed);
+        HEALTH.shutting_down.store(true, Ordering::Relaxed);
+        self.subscriptions.lock().unwrap().subscribers().len()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Reads the configuration file again and applies what can be changed without restarting.
+    /// The current configuration is kept if the file can't be read.
+    pub fn reload(&self, args: &Args) -> Result<(), String> {
+        self.notify("RELOADING=1");
+
+        let config = Config::load(args).inspect_err(|_| self.notify("READY=1"))?;
+
+        if let Err(e) = Logger::reopen(&config.log) {
+            error!("{e}");
+        }
+
+        self.subscriptions.lock().unwrap().reconfigure(config.subscriptions);
+        {
+            let mut cache = self.cache.lock();
+            cache.reconfigure(config.cache, config.privacy, config.opt_out, config.details);
+
+            let relay = config.relay.enabled.then(|| Relay::start(config.relay, cache.relay().map(Arc::as_ref)));
+
+            if let Some(previous) = cache.set_relay(relay) {
+                previous.stop();
+            }
+        }
+
+        match AuditLog::new(config.audit) {
+            Ok(audit) => *self.audit.lock().unwrap() = audit,
+            Err(e) => error!("Failed to open the audit log, keeping the current one: {e}")
+        }
+
+        info!("Reloaded configuration from {}", config.path.display());
+        self.notify("READY=1");
+        Ok(())
+    }
+
+    /// Shuts down once no request has been handled and no client has been subscribed for `timeout`.
+    fn start_idle_timer(server: Arc<Self>, timeout: Duration) {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+
+            let idle = server.last_activity.lock().unwrap().elapsed() >= timeout
+                && server.subscriptions.lock().unwrap().subscribers().is_empty();
+
+            if idle {
+                info!("Exiting after {} seconds without requests", timeout.as_secs());
+                server.shutdown();
+                return;
+            }
+        });
+    }
+
+    fn receive_request(&self, socket: &Arc<UdpSocket>, pool: &WorkerPool<Job>) -> WhereResult<()> {
+        let mut buf = [0; MAX_REQUEST_LENGTH];
+
+        let (len, src) = socket.recv_from(&mut buf)?;
+        *self.last_activity.lock().unwrap() = Instant::now();
+
+        let request = Request::from_bytes(&buf[..len])
+            .inspect_err(|_| {
+                Metrics::increment(&METRICS.requests_rejected);
+                self.audit(src, None, None, Outcome::Rejected);
+            })?;
+
+        let job = Job::Request {
+            socket: socket.clone(),
+            src,
+            request
+        };
+
+        if pool.submit(job).is_err() {
+            Metrics::increment(&METRICS.requests_rate_limited);
+            self.audit(src, Some(request), None, Outcome::RateLimited);
+            debug!("{src}: Dropped request, all workers are busy");
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&self, socket: &Arc<UdpSocket>, src: SocketAddr, request: Request) -> WhereResult<()> {
+        match request {
+            Request::Query | Request::ExtendedQuery => {
+                debug!("{src}: New client!");
+
+                let extended = request == Request::ExtendedQuery;
+                let (buf, sessions) = self.cache.snapshot().payload(src.ip(), extended, false)?;
+
+                let sent = socket.send_to(&buf, src)?;
+                Metrics::add(&METRICS.bytes_sent, sent as u64);
+                self.audit(src, Some(request), Some(sessions), Outcome::Served);
+                debug!("{src}: Completed request within {} bytes", buf.len());
+            }
+            Request::Subscribe(lease, cookie) => {
+                let (granted, cookie) = if self.draining.load(Ordering::Relaxed) {
+                    (0, cookie.map(|_| 0))
+                } else {
+                    self.subscriptions.lock().unwrap().subscribe(src, socket.clone(), lease, cookie)
+                };
+                let sent = socket.send_to(&subscription_ack(granted, cookie), src)?;
+                Metrics::add(&METRICS.bytes_sent, sent as u64);
+
+                if granted > 0 {
+                    self.audit(src, Some(request), None, Outcome::Served);
+                    debug!("{src}: Subscribed for {granted} seconds");
+                } else if cookie.is_some_and(|cookie| cookie != 0) {
+                    // The client has to prove it is at this address first
+                    self.audit(src, Some(request), None, Outcome::Refused);
+                    debug!("{src}: Sent a subscription cookie");
+                } else {
+                    self.audit(src, Some(request), None, Outcome::Refused);
+                    debug!("{src}: Refused subscription");
+                }
+            }
+            Request::Unsubscribe => {
+                if self.subscriptions.lock().unwrap().unsubscribe(&src) {
+                    debug!("{src}: Unsubscribed");
+                }
+
+                self.audit(src, Some(request), None, Outcome::Served);
+            }
+        }
+
+        Metrics::increment(&METRICS.requests_served);
+        Ok(())
+    }
+
+    /// Answers a request that came over TCP.  Subscriptions are refused, as changes are sent
+    /// as datagrams.
+    pub fn stream_response(&self, src: SocketAddr, request: Request) -> WhereResult<Option<Vec<u8>>> {
+        *self.last_activity.lock().unwrap() = Instant::now();
+
+        let response = match request {
+            Request::Query | Request::ExtendedQuery => {
+                let extended = request == Request::ExtendedQuery;
+                let (buf, sessions) = self.cache.snapshot().payload(src.ip(), extended, true)?;
+                self.audit(src, Some(request), Some(sessions), Outcome::Served);
+                Some(buf)
+            }
+            Request::Subscribe(_, cookie) => {
+                self.audit(src, Some(request), None, Outcome::Refused);
+                Some(subscription_ack(0, cookie.map(|_| 0)))
+            }
+            Request::Unsubscribe => {
+                self.audit(src, Some(request), None, Outcome::Served);
+                None
+            }
+        };
+
+        Metrics::increment(&METRICS.requests_served);
+        Ok(response)
+    }
+
+    pub fn audit(&self, source: SocketAddr, request: Option<Request>, sessions: Option<usize>, outcome: Outcome) {
+        self.audit_record(AuditRecord {
+            source,
+            request: request.map(audit::request_name),
+            filters: vec![],
+            sessions,
+            outcome
+        });
+    }
+
+    pub fn audit_record(&self, record: AuditRecord) {
+        let mut audit = self.audit.lock().unwrap();
+
+        let Some(audit) = audit.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = audit.write(&record) {
+            error!("Failed to write to the audit log: {e}");
+        }
+    }
+
+    /// Returns the current sessions as a client is allowed to see them.
+    pub fn sessions_for(&self, client: IpAddr) -> SessionCollection {
+        self.cache.snapshot().sessions_for(client)
+    }
+
+    pub fn subscriptions(&self) -> &Mutex<Subscriptions> {
+        &self.subscriptions
+    }
+
+    pub fn cache(&self) -> &SharedCache {
+        &self.cache
+    }
+}
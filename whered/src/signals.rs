@@ -0,0 +1,28 @@
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use log::{error, info};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use crate::args::Args;
+use crate::server::Server;
+
+/// Reloads the configuration on SIGHUP, and shuts down gracefully on SIGTERM or SIGINT.
+pub fn start_handler(server: Arc<Server>, args: Args) -> io::Result<()> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                if let Err(e) = server.reload(&args) {
+                    error!("{e}, keeping the current configuration");
+                }
+            } else {
+                info!("Finishing pending requests and shutting down");
+                server.shutdown();
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::io::{self, Read, StdinLock, StdoutLock, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use log::debug;
+use whrd::error::WhereResult;
+use crate::config::Config;
+use crate::registry;
+use crate::server::Server;
+use crate::tcp;
+
+/// Standard input and output, read and written as one stream.
+struct Pipe<'a> {
+    stdin: StdinLock<'a>,
+    stdout: StdoutLock<'a>
+}
+
+/// Answers the requests framed on standard input on standard output until it is closed, for
+/// clients that reach whered by running it through SSH.  They get the same answers as over
+/// TCP, as far as a process of its own can know about them.
+pub fn serve(config: Config) -> WhereResult<()> {
+    // Privacy rules apply to whoever is at the other end of the SSH connection
+    let client = ssh_client().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let src = SocketAddr::new(client, 0);
+    let server = Server::new(&config, None)?;
+
+    if let Some(relay) = server.cache().lock().relay() {
+        relay.wait_for_first_answers();
+    }
+
+    let mut pipe = Pipe {
+        stdin: io::stdin().lock(),
+        stdout: io::stdout().lock()
+    };
+
+    loop {
+        // Sessions are registered with the daemon, which may have more of them by now
+        if config.registration.enabled {
+            let mut cache = server.cache().lock();
+
+            if let Err(e) = registry::copy_from_daemon(&config.registration, cache.registry()) {
+                debug!("Unable to list the sessions registered with the daemon: {e}");
+            }
+        }
+
+        if !tcp::answer(&mut pipe, src, &server)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Finds the client's address in the variables sshd sets.
+fn ssh_client() -> Option<IpAddr> {
+    ["SSH_CONNECTION", "SSH_CLIENT"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find_map(|value| value.split_whitespace().next()?.parse().ok())
+}
+
+impl Read for Pipe<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl Write for Pipe<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::collections::hash_map::Entry;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, warn};
+use whrd::SessionCollection;
+use whrd::subscription::SessionEvent;
+use crate::config::SubscriptionConfig;
+use crate::metrics::{Metrics, METRICS};
+use crate::privacy;
+use crate::server::Server;
+
+const DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Cookies stay valid for one to two periods, which leaves clients time to renew
+const COOKIE_PERIOD: u64 = 300;
+
+struct Subscriber {
+    socket: Arc<UdpSocket>,
+    expiry: Instant
+}
+
+pub struct Subscriptions {
+    subscribers: HashMap<SocketAddr, Subscriber>,
+    config: SubscriptionConfig,
+    // A secret that changes every time whered starts, which cookies are derived from
+    cookie_key: RandomState,
+    started_at: Instant
+}
+
+impl Subscriptions {
+    pub fn new(config: SubscriptionConfig) -> Self {
+        Self {
+            subscribers: HashMap::new(),
+            config,
+            cookie_key: RandomState::new(),
+            started_at: Instant::now()
+        }
+    }
+
+    /// Adds or renews a subscription and returns the granted lease in seconds, 0 if refused,
+    /// along with the cookie to acknowledge it with.  Anyone can send a datagram from someone
+    /// else's address, so there is no subscription until the client sends back a cookie it
+    /// could only have received at its address.
+    pub fn subscribe(&mut self, addr: SocketAddr, socket: Arc<UdpSocket>, lease: u32, cookie: Option<u64>) -> (u32, Option<u64>) {
+        self.prune();
+
+        let lease = lease.min(self.config.max_lease);
+        let is_renewal = self.subscribers.contains_key(&addr);
+
+        if !self.config.enabled || lease == 0 || (!is_renewal && self.subscribers.len() >= self.config.max_subscribers) {
+            return (0, cookie.map(|_| 0));
+        }
+
+        // Clients that don't know about cookies can't subscribe
+        let Some(cookie) = cookie else {
+            return (0, None);
+        };
+
+        let period = self.started_at.elapsed().as_secs() / COOKIE_PERIOD;
+        let fresh_cookie = self.cookie(addr, period);
+
+        if cookie != fresh_cookie && (period == 0 || cookie != self.cookie(addr, period - 1)) {
+            return (0, Some(fresh_cookie));
+        }
+
+        self.subscribers.insert(addr, Subscriber {
+            socket,
+            expiry: Instant::now() + Duration::from_secs(lease as u64)
+        });
+        (lease, Some(fresh_cookie))
+    }
+
+    fn cookie(&self, addr: SocketAddr, period: u64) -> u64 {
+        // 0 asks for a cookie, so it can't be one
+        self.cookie_key.hash_one((addr, period)).max(1)
+    }
+
+    /// Applies a new configuration, keeping the current subscribers unless subscriptions were
+    /// disabled.
+    pub fn reconfigure(&mut self, config: SubscriptionConfig) {
+        if !config.enabled {
+            self.subscribers.clear();
+        }
+
+        self.config = config;
+    }
+
+    pub fn poll_interval(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.config.poll_interval))
+            .filter(|_| self.config.enabled)
+    }
+
+    pub fn unsubscribe(&mut self, addr: &SocketAddr) -> bool {
+        self.subscribers.remove(addr).is_some()
+    }
+
+    /// Lists the current subscribers, along with the socket they subscribed through.
+    pub fn subscribers(&mut self) -> Vec<(SocketAddr, Arc<UdpSocket>)> {
+        self.prune();
+        self.subscribers.iter()
+            .map(|(addr, subscriber)| (*addr, subscriber.socket.clone()))
+            .collect()
+    }
+
+    /// Lists the current subscribers, along with how long until their subscription expires.
+    pub fn leases(&mut self) -> Vec<(SocketAddr, Duration)> {
+        self.prune();
+
+        let now = Instant::now();
+        self.subscribers.iter()
+            .map(|(addr, subscriber)| (*addr, subscriber.expiry - now))
+            .collect()
+    }
+
+    fn prune(&mut self) {
+        let now = Instant::now();
+        self.subscribers.retain(|_, subscriber| subscriber.expiry > now);
+    }
+}
+
+/// Watches for session changes and notifies subscribers, for as long as subscriptions are
+/// enabled in the configuration.
+pub fn start_watcher(server: Arc<Server>) {
+    thread::spawn(move || {
+        let mut previous: Option<(u64, SessionCollection)> = None;
+
+        loop {
+            let Some(interval) = server.subscriptions().lock().unwrap().poll_interval() else {
+                // Check again later in case the configuration is reloaded
+                previous = None;
+                thread::sleep(DISABLED_POLL_INTERVAL);
+                continue;
+            };
+
+            thread::sleep(interval);
+
+            let (generation, current, rules) = {
+                let snapshot = server.cache().snapshot();
+                // Events can't say which host a session is on, so relayed ones are left out
+                let sessions = snapshot.sessions.clone().into_vec().into_iter()
+                    .filter(|session| session.host.is_none())
+                    .collect();
+                (snapshot.generation, SessionCollection::from_vec(sessions), snapshot.privacy().to_vec())
+            };
+
+            let events = match previous.replace((generation, current)) {
+                Some((previous_generation, _)) if previous_generation == generation => continue,
+                Some((_, previous_sessions)) => previous_sessions.diff(&previous.as_ref().unwrap().1),
+                None => continue
+            };
+
+            if events.is_empty() {
+                continue;
+            }
+
+            let subscribers = server.subscriptions().lock().unwrap().subscribers();
+
+            if subscribers.is_empty() {
+                continue;
+            }
+
+            let event_count = events.len();
+            // Events encoded for every privacy rule set used by the subscribers
+            let mut payloads = HashMap::new();
+
+            for (subscriber, socket) in &subscribers {
+                let rule = privacy::rule_for(&rules, subscriber.ip());
+
+                let payloads = match payloads.entry(rule) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let events = match rule {
+                            Some(index) => rules[index].apply_events(&events),
+                            None => events.clone()
+                        };
+
+                        match SessionEvent::to_udp_payloads(events) {
+                            Ok(encoded) => entry.insert(encoded),
+                            Err(e) => {
+                                Metrics::increment(&METRICS.encode_errors);
+                                error!("{e}");
+                                continue;
+                            }
+                        }
+                    }
+                };
+
+                for payload in payloads.iter() {
+                    match socket.send_to(payload, subscriber) {
+                        Ok(sent) => Metrics::add(&METRICS.bytes_sent, sent as u64),
+                        Err(e) => warn!("{subscriber}: {e}")
+                    }
+                }
+            }
+
+            debug!("Notified {} subscribers of {event_count} session changes", subscribers.len());
+        }
+    });
+}
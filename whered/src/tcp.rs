@@ -0,0 +1,90 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use log::debug;
+use whrd::error::WhereResult;
+use whrd::request::Request;
+use whrd::{stream, MAX_REQUEST_LENGTH};
+use crate::audit::Outcome;
+use crate::metrics::{Metrics, METRICS};
+use crate::pool::ConnectionLimit;
+use crate::server::Server;
+
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers requests framed with their length over TCP, for responses too large for a
+/// datagram.  Every connection carries a single request.
+pub fn start_listener(listener: TcpListener, server: Arc<Server>, max_connections: usize) {
+    let limit = ConnectionLimit::new(max_connections);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TCP: {e}");
+                    continue;
+                }
+            };
+
+            let res = limit.spawn(stream, {
+                let server = server.clone();
+
+                move |stream| {
+                    if let Err(e) = handle_connection(stream, &server) {
+                        debug!("TCP: {e}");
+                    }
+                }
+            });
+
+            if let Err(stream) = res {
+                rate_limited(&server, stream.peer_addr().ok());
+            }
+        }
+    });
+}
+
+fn handle_connection(mut connection: TcpStream, server: &Server) -> WhereResult<()> {
+    connection.set_read_timeout(Some(TCP_TIMEOUT))?;
+    connection.set_write_timeout(Some(TCP_TIMEOUT))?;
+
+    let src = connection.peer_addr()?;
+    answer(&mut connection, src, server).map(drop)
+}
+
+/// Accounts for a connection closed right away because too many are open.
+pub fn rate_limited(server: &Server, src: Option<SocketAddr>) {
+    Metrics::increment(&METRICS.requests_rate_limited);
+
+    if let Some(src) = src {
+        server.audit(src, None, None, Outcome::RateLimited);
+        debug!("{src}: Closed connection, too many are open");
+    }
+}
+
+/// Reads one request from a stream and writes the response back, if there is one.  Returns
+/// whether there was a request, rather than the end of the stream.
+pub fn answer(connection: &mut (impl Read + Write), src: SocketAddr, server: &Server) -> WhereResult<bool> {
+    let Some(frame) = stream::read_frame(connection, MAX_REQUEST_LENGTH)? else {
+        return Ok(false);
+    };
+
+    let request = Request::from_bytes(&frame)
+        .inspect_err(|_| {
+            Metrics::increment(&METRICS.requests_rejected);
+            server.audit(src, None, None, Outcome::Rejected);
+        })?;
+
+    let response = server.stream_response(src, request)
+        .inspect_err(|_| server.audit(src, Some(request), None, Outcome::Failed))?;
+
+    if let Some(response) = response {
+        stream::write_frame(connection, &response)?;
+        Metrics::add(&METRICS.bytes_sent, response.len() as u64);
+        debug!("{src}: Completed request within {} bytes over a stream", response.len());
+    }
+
+    Ok(true)
+}
@@ -2,6 +2,7 @@ use std::fmt::Display;
 use std::string::FromUtf8Error;
 use std::{fmt, io};
 use std::net::AddrParseError;
+use std::process::ExitStatus;
 use std::time::Duration;
 use crate::{MAX_ENTRY_LENGTH, MAX_PAYLOAD_LENGTH};
 
@@ -9,14 +10,21 @@ pub enum WhereError {
     EncodeDecodeError(EncodeDecodeError),
     IOError(io::Error),
     TimedOut(String, String, usize, Duration),
-    CannotParseAddress(AddrParseError)
+    CannotParseAddress(AddrParseError),
+    CommandFailed(String, ExitStatus)
 }
 
 pub enum EncodeDecodeError {
     InvalidEntryLength(usize),
     InvalidPayloadLength(usize),
+    InvalidRequestLength(usize),
+    InvalidExtensionLength(usize),
+    InvalidFrameLength(usize),
+    InvalidAckLength(usize),
+    UnsupportedRwhoPacket(u8, u8),
     BadMagic([u8; 4]),
     IncorrectEntryCount,
+    TruncatedPayload,
     StringSizeLimitExceeded(u32, usize),
     StringDecodeError(FromUtf8Error),
     NonbinaryBoolean,
@@ -68,8 +76,14 @@ impl Display for EncodeDecodeError {
         match self {
             Self::InvalidEntryLength(s) => write!(f, "Invalid entry length: {s} but maximum is {MAX_ENTRY_LENGTH}"),
             Self::InvalidPayloadLength(s) => write!(f, "Invalid full payload length: {s} but maximum is {MAX_PAYLOAD_LENGTH}"),
+            Self::InvalidRequestLength(s) => write!(f, "Invalid request length: {s} bytes"),
+            Self::InvalidExtensionLength(s) => write!(f, "Invalid extension length: {s} bytes"),
+            Self::InvalidFrameLength(s) => write!(f, "Invalid frame length: {s} bytes"),
+            Self::InvalidAckLength(s) => write!(f, "Invalid subscription acknowledgement length: {s} bytes"),
+            Self::UnsupportedRwhoPacket(version, kind) => write!(f, "Unsupported rwho packet (version {version}, type {kind})"),
             Self::BadMagic(m) => write!(f, "Invalid packet magic ({}), possible corruption or invalid server", String::from_utf8_lossy(m)),
             Self::IncorrectEntryCount => write!(f, "Invalid amount of entries decoded"),
+            Self::TruncatedPayload => write!(f, "Response too large for UDP, it has to be asked for over TCP"),
             Self::StringDecodeError(e) => write!(f, "String decoding error: {e}"),
             Self::StringSizeLimitExceeded(curr, max) => write!(f, "Exceeded length limit for payload string ({curr} > {max})"),
             Self::NonbinaryBoolean => write!(f, "Boolean value is not 0 or 1"),
@@ -85,7 +99,8 @@ impl Display for WhereError {
             Self::EncodeDecodeError(e) => write!(f, "Encode/decode error: {e}"),
             Self::IOError(e) => write!(f, "Input/output error: {e}"),
             Self::TimedOut(server, address, max_retry, timeout) => write!(f, "Timed out waiting for data from {server} ({address}) after {max_retry} attempts every {} ms", timeout.as_millis()),
-            Self::CannotParseAddress(e) => write!(f, "Unable to parse server address: {e}")
+            Self::CannotParseAddress(e) => write!(f, "Unable to parse server address: {e}"),
+            Self::CommandFailed(command, status) => write!(f, "Command '{command}' failed ({status})")
         }
     }
 }
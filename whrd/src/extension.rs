@@ -0,0 +1,91 @@
+use std::io::Read;
+
+use crate::error::{EncodeDecodeError, EncodeDecodeResult, WhereResult};
+use crate::{parse, PayloadCursor, Session, MAX_EXTENSION_LENGTH, MAX_HOST_LENGTH, MAX_ORIGIN_LENGTH, MAX_REAL_NAME_LENGTH, MAX_STATUS_LENGTH};
+
+// Every field in the extension block is a tag, a 32-bit length and the value, so that
+// clients can skip the fields they don't know about
+const TAG_REAL_NAME: u8 = 1;
+const TAG_STATUS: u8 = 2;
+const TAG_ORIGIN: u8 = 3;
+const TAG_HOST: u8 = 4;
+
+/// Encodes the fields that only extended responses carry.
+pub fn encode(session: &Session) -> Vec<u8> {
+    let mut fields: Vec<u8> = vec![];
+
+    for (tag, value) in [
+        (TAG_REAL_NAME, &session.real_name),
+        (TAG_STATUS, &session.status),
+        (TAG_ORIGIN, &session.origin),
+        (TAG_HOST, &session.host)
+    ] {
+        if let Some(value) = value {
+            fields.push(tag);
+            fields.extend(&(value.len() as u32).to_be_bytes());
+            fields.extend(value.as_bytes());
+        }
+    }
+
+    let mut bytes: Vec<u8> = vec![];
+    bytes.extend(&(fields.len() as u16).to_be_bytes());
+    bytes.extend(fields);
+
+    bytes
+}
+
+/// Decodes an extension block into a session that was just read.
+pub fn decode(cursor: &mut PayloadCursor, session: &mut Session) -> WhereResult<()> {
+    let length = parse::read_field(cursor, |buf| Ok(u16::from_be_bytes(buf)))? as usize;
+
+    if length > MAX_EXTENSION_LENGTH {
+        Err(EncodeDecodeError::InvalidExtensionLength(length))?
+    }
+
+    let mut fields = vec![0u8; length];
+    cursor.read_exact(&mut fields)?;
+
+    let mut rest = fields.as_slice();
+
+    while !rest.is_empty() {
+        let (tag, value, remaining) = read_tlv(rest)?;
+        rest = remaining;
+
+        match tag {
+            TAG_REAL_NAME => session.real_name = Some(read_string(value, MAX_REAL_NAME_LENGTH)?),
+            TAG_STATUS => session.status = Some(read_string(value, MAX_STATUS_LENGTH)?),
+            TAG_ORIGIN => session.origin = Some(read_string(value, MAX_ORIGIN_LENGTH)?),
+            // Relayed sessions are shown under the relay, then the host they come from
+            TAG_HOST => {
+                let host = read_string(value, MAX_HOST_LENGTH)?;
+
+                session.host = Some(match &session.host {
+                    Some(relay) => format!("{relay}/{host}"),
+                    None => host
+                });
+            },
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn read_tlv(buf: &[u8]) -> EncodeDecodeResult<(u8, &[u8], &[u8])> {
+    let malformed = || EncodeDecodeError::InvalidExtensionLength(buf.len());
+
+    let (&tag, rest) = buf.split_first().ok_or_else(malformed)?;
+    let (length, rest) = rest.split_at_checked(4).ok_or_else(malformed)?;
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+
+    let (value, rest) = rest.split_at_checked(length).ok_or_else(malformed)?;
+    Ok((tag, value, rest))
+}
+
+fn read_string(value: &[u8], max_length: usize) -> EncodeDecodeResult<String> {
+    if value.len() > max_length {
+        return Err(EncodeDecodeError::StringSizeLimitExceeded(value.len() as u32, max_length));
+    }
+
+    Ok(String::from_utf8(value.to_vec())?)
+}
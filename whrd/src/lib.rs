@@ -1,25 +1,48 @@
 use std::io::Cursor;
+use std::path::PathBuf;
 
 #[cfg(unix)]
 use coreutils_core::os::utmpx::*;
 
 use crate::error::{WhereResult, EncodeDecodeResult, EncodeDecodeError};
 
+mod extension;
 mod parse;
 pub mod error;
+pub mod request;
+pub mod rwho;
+pub mod stream;
+pub mod subscription;
 
 pub const WHERED_MAGIC: [u8; 4] = *b"WHRD";
+pub const WHERED_SUBSCRIBE_MAGIC: [u8; 4] = *b"WHRS";
+pub const WHERED_UNSUBSCRIBE_MAGIC: [u8; 4] = *b"WHRU";
+pub const WHERED_NOTIFY_MAGIC: [u8; 4] = *b"WHRN";
+pub const WHERED_EXTENDED_MAGIC: [u8; 4] = *b"WHRX";
+/// Sent instead of a response that doesn't fit in a datagram, to ask for it over TCP
+pub const WHERED_TRUNCATED_MAGIC: [u8; 4] = *b"WHRT";
+pub const MAX_REQUEST_LENGTH: usize = 16;
 pub const MAX_USER_TTY_LENGTH: usize = 32;
 pub const MAX_REMOTE_LENGTH: usize = 64;
 pub const MAX_ENTRY_LENGTH: usize = MAX_REMOTE_LENGTH + MAX_USER_TTY_LENGTH * 2 + 25;
 pub const MAX_PAYLOAD_LENGTH: usize = 65501;
 pub const MAX_PAYLOAD_ENTRIES: usize = MAX_PAYLOAD_LENGTH / MAX_ENTRY_LENGTH;
+pub const MAX_REAL_NAME_LENGTH: usize = 64;
+pub const MAX_STATUS_LENGTH: usize = 256;
+pub const MAX_ORIGIN_LENGTH: usize = 32;
+pub const MAX_HOST_LENGTH: usize = 128;
+pub const MAX_EXTENSION_LENGTH: usize = 1024;
+pub const MAX_EXTENDED_ENTRY_LENGTH: usize = MAX_ENTRY_LENGTH + 2 + MAX_EXTENSION_LENGTH;
+/// Responses over TCP or pipes aren't limited by datagrams, but still have to end somewhere
+pub const MAX_STREAM_PAYLOAD_LENGTH: usize = 16 * 1024 * 1024;
 
 type Payload = [u8; MAX_PAYLOAD_LENGTH];
-type PayloadCursor = Cursor<Payload>;
+type PayloadCursor<'a> = Cursor<&'a [u8]>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Session {
+    /// The label of the server the session was fetched from.  Servers only set it, in
+    /// extended responses, for sessions they relay from another one.
     pub host: Option<String>,
     pub pid: i32,
     pub login_time: i64,
@@ -27,9 +50,16 @@ pub struct Session {
     pub tty: String,
     pub remote: Option<String>,
     pub active: bool,
+    /// Only sent in extended responses
+    pub real_name: Option<String>,
+    /// Only sent in extended responses
+    pub status: Option<String>,
+    /// What created the session when it isn't in utmp, such as a web terminal.  Only sent in
+    /// extended responses.
+    pub origin: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SessionCollection {
     inner: Vec<Session>
 }
@@ -58,26 +88,64 @@ impl SessionCollection {
         self.inner
     }
 
+    pub fn from_vec(inner: Vec<Session>) -> Self {
+        Self {
+            inner
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub fn to_udp_payload(self) -> EncodeDecodeResult<Vec<u8>> {
-        println!("Encoding payload with {} entries", self.inner.len());
+        self.encode(false, MAX_PAYLOAD_LENGTH)
+    }
+
+    /// Encodes the sessions along with the fields that only extended queries ask for.
+    pub fn to_extended_udp_payload(self) -> EncodeDecodeResult<Vec<u8>> {
+        self.encode(true, MAX_PAYLOAD_LENGTH)
+    }
+
+    /// Encodes the sessions for TCP or a pipe, which allows for more of them than a datagram.
+    pub fn to_stream_payload(self, extended: bool) -> EncodeDecodeResult<Vec<u8>> {
+        self.encode(extended, MAX_STREAM_PAYLOAD_LENGTH)
+    }
+
+    fn encode(self, extended: bool, max_payload_length: usize) -> EncodeDecodeResult<Vec<u8>> {
+        log::debug!("Encoding payload with {} entries", self.inner.len());
 
         let mut bytes: Vec<u8> = vec![];
-        bytes.extend(&WHERED_MAGIC);
+        bytes.extend(if extended { &WHERED_EXTENDED_MAGIC } else { &WHERED_MAGIC });
 
-        let entry_count = (self.inner.len() as u16).to_be_bytes();
+        let entry_count = u16::try_from(self.inner.len())
+            .map_err(|_| EncodeDecodeError::IncorrectEntryCount)?
+            .to_be_bytes();
         bytes.extend(&entry_count);
 
         for item in self.inner {
-            let entry = item.to_udp_payload();
+            let entry = if extended {
+                let mut entry = extension::encode(&item);
+                entry.splice(0..0, item.to_udp_payload());
+                entry
+            } else {
+                item.to_udp_payload()
+            };
+
+            let max_length = if extended { MAX_EXTENDED_ENTRY_LENGTH } else { MAX_ENTRY_LENGTH };
 
-            if entry.len() > MAX_ENTRY_LENGTH {
+            if entry.len() > max_length {
                 return Err(EncodeDecodeError::InvalidEntryLength(entry.len()));
             }
 
             bytes.extend(entry);
         }
 
-        if bytes.len() > MAX_PAYLOAD_LENGTH {
+        if bytes.len() > max_payload_length {
             Err(EncodeDecodeError::InvalidPayloadLength(bytes.len()))
         } else {
             Ok(bytes)
@@ -85,22 +153,34 @@ impl SessionCollection {
     }
 
     pub fn from_udp_payload(buffer: Payload, host: &str) -> WhereResult<Self> {
+        Self::from_payload(&buffer, host)
+    }
+
+    /// Decodes a response, whether it came in a datagram or over a stream.
+    pub fn from_payload(buffer: &[u8], host: &str) -> WhereResult<Self> {
         let mut cursor = Cursor::new(buffer);
         let mut inner = vec![];
 
-        // Check magic
-        parse::read_field(&mut cursor, |buf| {
-            if buf != WHERED_MAGIC {
-                Err(EncodeDecodeError::BadMagic(buf))?
-            } else {
-                Ok(())
+        // Check magic, which also tells whether entries are extended
+        let extended = parse::read_field(&mut cursor, |buf| {
+            match buf {
+                WHERED_MAGIC => Ok(false),
+                WHERED_EXTENDED_MAGIC => Ok(true),
+                WHERED_TRUNCATED_MAGIC => Err(EncodeDecodeError::TruncatedPayload)?,
+                _ => Err(EncodeDecodeError::BadMagic(buf))?
             }
         })?;
 
         let entry_count = parse::read_field(&mut cursor, |buf| Ok(u16::from_be_bytes(buf)))?;
 
         for _ in 0..entry_count {
-            inner.push(Session::from_udp_payload(&mut cursor, host)?);
+            let mut session = Session::from_udp_payload(&mut cursor, host)?;
+
+            if extended {
+                extension::decode(&mut cursor, &mut session)?;
+            }
+
+            inner.push(session);
         }
 
         Ok(Self {
@@ -137,6 +217,9 @@ impl Session {
             tty,
             remote,
             active,
+            real_name: None,
+            status: None,
+            origin: None,
         })
     }
 
@@ -202,6 +285,8 @@ impl From<Utmpx> for Session {
         let mut path = PathBuf::from("/dev");
         path.push(utmpx.device_name().to_string());
         let active = utmpx.entry_type() == UtmpxKind::UserProcess && utmpx.is_active();
+        // time_t is only 32 bits wide on some targets
+        #[allow(clippy::unnecessary_cast)]
         let login_time = utmpx.timeval().tv_sec as i64;
 
         Self {
@@ -211,7 +296,10 @@ impl From<Utmpx> for Session {
             tty,
             remote,
             active,
-            login_time
+            login_time,
+            real_name: None,
+            status: None,
+            origin: None
         }
     }
 }
@@ -0,0 +1,63 @@
+use crate::error::{EncodeDecodeError, EncodeDecodeResult};
+use crate::{MAX_REQUEST_LENGTH, WHERED_EXTENDED_MAGIC, WHERED_MAGIC, WHERED_SUBSCRIBE_MAGIC, WHERED_UNSUBSCRIBE_MAGIC};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Query,
+    /// Like a query, but the response also carries the fields that only recent servers know
+    ExtendedQuery,
+    /// A lease in seconds, then the cookie from the server's last acknowledgement, 0 to get
+    /// one.  Clients from before cookies don't send any.
+    Subscribe(u32, Option<u64>),
+    Unsubscribe,
+}
+
+impl Request {
+    pub fn from_bytes(buf: &[u8]) -> EncodeDecodeResult<Self> {
+        if buf.len() < WHERED_MAGIC.len() || buf.len() > MAX_REQUEST_LENGTH {
+            return Err(EncodeDecodeError::InvalidRequestLength(buf.len()));
+        }
+
+        let (magic, rest) = buf.split_at(WHERED_MAGIC.len());
+        let magic: [u8; 4] = magic.try_into().unwrap();
+
+        match magic {
+            WHERED_MAGIC => Ok(Self::Query),
+            WHERED_EXTENDED_MAGIC => Ok(Self::ExtendedQuery),
+            WHERED_SUBSCRIBE_MAGIC => {
+                let (lease, cookie) = match rest.len() {
+                    4 => (rest, None),
+                    12 => {
+                        let (lease, cookie) = rest.split_at(4);
+                        (lease, Some(u64::from_be_bytes(cookie.try_into().unwrap())))
+                    },
+                    _ => return Err(EncodeDecodeError::InvalidRequestLength(buf.len()))
+                };
+
+                Ok(Self::Subscribe(u32::from_be_bytes(lease.try_into().unwrap()), cookie))
+            },
+            WHERED_UNSUBSCRIBE_MAGIC => Ok(Self::Unsubscribe),
+            _ => Err(EncodeDecodeError::BadMagic(magic))
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![];
+
+        match self {
+            Self::Query => bytes.extend(&WHERED_MAGIC),
+            Self::ExtendedQuery => bytes.extend(&WHERED_EXTENDED_MAGIC),
+            Self::Subscribe(lease, cookie) => {
+                bytes.extend(&WHERED_SUBSCRIBE_MAGIC);
+                bytes.extend(&lease.to_be_bytes());
+
+                if let Some(cookie) = cookie {
+                    bytes.extend(&cookie.to_be_bytes());
+                }
+            },
+            Self::Unsubscribe => bytes.extend(&WHERED_UNSUBSCRIBE_MAGIC),
+        }
+
+        bytes
+    }
+}
@@ -0,0 +1,160 @@
+use crate::error::{EncodeDecodeError, EncodeDecodeResult};
+use crate::{Session, SessionCollection};
+
+pub const WHODVERSION: u8 = 1;
+pub const WHODTYPE_STATUS: u8 = 1;
+const HOSTNAME_LENGTH: usize = 32;
+const LINE_LENGTH: usize = 8;
+const NAME_LENGTH: usize = 8;
+// Version, type, padding, send and receive times, host name, load averages and boot time
+pub const WHOD_HEADER_LENGTH: usize = 4 + 4 + 4 + HOSTNAME_LENGTH + 3 * 4 + 4;
+pub const WHOD_ENTRY_LENGTH: usize = LINE_LENGTH + NAME_LENGTH + 4 + 4;
+/// rwhod never sends more entries than fit in 1024 bytes
+pub const MAX_WHOD_ENTRIES: usize = 1024 / WHOD_ENTRY_LENGTH;
+
+/// One logged in user, as broadcast by rwhod.  Names longer than 8 bytes are cut.
+#[derive(Debug, Clone)]
+pub struct WhodEntry {
+    pub tty: String,
+    pub user: String,
+    pub login_time: i64,
+    pub idle: i32,
+}
+
+/// A status packet from rwhod, which is also what rwho spool files contain.
+#[derive(Debug, Clone)]
+pub struct Whod {
+    pub send_time: i64,
+    /// Set by the receiver when the packet is written to the spool
+    pub recv_time: i64,
+    pub hostname: String,
+    /// Load averages over 1, 5 and 15 minutes, times 100
+    pub load_average: [i32; 3],
+    pub boot_time: i64,
+    pub entries: Vec<WhodEntry>,
+}
+
+impl Whod {
+    /// Builds a status packet from the active sessions, as many as fit in one packet.
+    pub fn from_sessions(hostname: &str, sessions: &SessionCollection, idle: impl Fn(&Session) -> i32) -> Self {
+        let entries = sessions.inner.iter()
+            .filter(|session| session.active)
+            .take(MAX_WHOD_ENTRIES)
+            .map(|session| WhodEntry {
+                tty: session.tty.clone(),
+                user: session.user.clone(),
+                login_time: session.login_time,
+                idle: idle(session),
+            })
+            .collect();
+
+        Self {
+            send_time: 0,
+            recv_time: 0,
+            hostname: hostname.to_string(),
+            load_average: [0; 3],
+            boot_time: 0,
+            entries,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![WHODVERSION, WHODTYPE_STATUS, 0, 0];
+
+        // Times are 32-bit in this protocol
+        bytes.extend(&(self.send_time as i32).to_be_bytes());
+        bytes.extend(&(self.recv_time as i32).to_be_bytes());
+        bytes.extend(fixed_string(&self.hostname, HOSTNAME_LENGTH));
+
+        for load in self.load_average {
+            bytes.extend(&load.to_be_bytes());
+        }
+
+        bytes.extend(&(self.boot_time as i32).to_be_bytes());
+
+        for entry in &self.entries {
+            bytes.extend(fixed_string(&entry.tty, LINE_LENGTH));
+            bytes.extend(fixed_string(&entry.user, NAME_LENGTH));
+            bytes.extend(&(entry.login_time as i32).to_be_bytes());
+            bytes.extend(&entry.idle.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Decodes a packet as it is sent over the network, in big-endian byte order.
+    pub fn from_bytes(buf: &[u8]) -> EncodeDecodeResult<Self> {
+        Self::decode(buf, i32::from_be_bytes)
+    }
+
+    /// Decodes a spool file, which rwhod writes in its own byte order after receiving a
+    /// packet.
+    pub fn from_spool_bytes(buf: &[u8]) -> EncodeDecodeResult<Self> {
+        Self::decode(buf, i32::from_ne_bytes)
+    }
+
+    fn decode(buf: &[u8], from_bytes: fn([u8; 4]) -> i32) -> EncodeDecodeResult<Self> {
+        if buf.len() < WHOD_HEADER_LENGTH || !(buf.len() - WHOD_HEADER_LENGTH).is_multiple_of(WHOD_ENTRY_LENGTH) {
+            return Err(EncodeDecodeError::InvalidPayloadLength(buf.len()));
+        }
+
+        if buf[0] != WHODVERSION || buf[1] != WHODTYPE_STATUS {
+            return Err(EncodeDecodeError::UnsupportedRwhoPacket(buf[0], buf[1]));
+        }
+
+        let int = |buf: &[u8], offset: usize| from_bytes(buf[offset..offset + 4].try_into().unwrap());
+
+        let entries = buf[WHOD_HEADER_LENGTH..].chunks_exact(WHOD_ENTRY_LENGTH)
+            .map(|entry| WhodEntry {
+                tty: read_fixed_string(&entry[..LINE_LENGTH]),
+                user: read_fixed_string(&entry[LINE_LENGTH..LINE_LENGTH + NAME_LENGTH]),
+                login_time: int(entry, 16) as i64,
+                idle: int(entry, 20),
+            })
+            .collect();
+
+        Ok(Self {
+            send_time: int(buf, 4) as i64,
+            recv_time: int(buf, 8) as i64,
+            hostname: read_fixed_string(&buf[12..12 + HOSTNAME_LENGTH]),
+            load_average: [int(buf, 44), int(buf, 48), int(buf, 52)],
+            boot_time: int(buf, 56) as i64,
+            entries,
+        })
+    }
+
+    /// Converts the packet into sessions, labelled with the host that sent it.  rwhod doesn't
+    /// send process IDs or remote hosts, so they are left empty.
+    pub fn into_sessions(self) -> SessionCollection {
+        let inner = self.entries.into_iter()
+            .map(|entry| Session {
+                host: Some(self.hostname.clone()),
+                pid: 0,
+                login_time: entry.login_time,
+                user: entry.user,
+                tty: entry.tty,
+                remote: None,
+                active: true,
+                real_name: None,
+                status: None,
+                origin: None,
+            })
+            .collect();
+
+        SessionCollection {
+            inner
+        }
+    }
+}
+
+/// Pads or cuts a string to a fixed-size field, as rwhod doesn't always NUL-terminate them.
+fn fixed_string(string: &str, length: usize) -> Vec<u8> {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.resize(length, 0);
+    bytes
+}
+
+fn read_fixed_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
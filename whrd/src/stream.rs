@@ -0,0 +1,35 @@
+use std::io::{ErrorKind, Read, Write};
+use crate::error::{EncodeDecodeError, EncodeDecodeResult};
+
+/// Writes a request or response to a byte stream, such as a pipe, where there are no
+/// datagrams to tell where it ends.  It is sent as is, after its length as a big-endian u32.
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> EncodeDecodeResult<()> {
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads a request or response written with [`write_frame`].  Returns nothing if the stream
+/// ended before the next one, and fails for ones longer than `max_length`.
+pub fn read_frame(reader: &mut impl Read, max_length: usize) -> EncodeDecodeResult<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+
+    match reader.read_exact(&mut length) {
+        Ok(()) => {},
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into())
+    }
+
+    let length = u32::from_be_bytes(length) as usize;
+
+    if length > max_length {
+        return Err(EncodeDecodeError::InvalidFrameLength(length));
+    }
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(payload))
+}
@@ -0,0 +1,150 @@
+use std::io::Cursor;
+
+use crate::error::{EncodeDecodeError, EncodeDecodeResult, WhereResult};
+use crate::{parse, Payload, Session, SessionCollection, MAX_ENTRY_LENGTH, MAX_PAYLOAD_LENGTH, WHERED_NOTIFY_MAGIC, WHERED_SUBSCRIBE_MAGIC};
+
+// Magic and entry count, then one kind byte in front of every entry
+const NOTIFY_HEADER_LENGTH: usize = WHERED_NOTIFY_MAGIC.len() + 2;
+pub const MAX_NOTIFY_EVENTS: usize = (MAX_PAYLOAD_LENGTH - NOTIFY_HEADER_LENGTH) / (MAX_ENTRY_LENGTH + 1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Ended,
+    Started,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    pub kind: EventKind,
+    pub session: Session,
+}
+
+impl Session {
+    fn is_same_session(&self, other: &Session) -> bool {
+        self.pid == other.pid && self.login_time == other.login_time && self.user == other.user && self.tty == other.tty
+    }
+}
+
+impl SessionCollection {
+    /// Lists the sessions that started or ended between this collection and a newer one.
+    pub fn diff(&self, newer: &SessionCollection) -> Vec<SessionEvent> {
+        let was_active = |session: &Session| self.inner.iter()
+            .any(|s| s.active && s.is_same_session(session));
+        let is_active = |session: &Session| newer.inner.iter()
+            .any(|s| s.active && s.is_same_session(session));
+
+        let ended = self.inner.iter()
+            .filter(|s| s.active && !is_active(s))
+            .map(|s| SessionEvent {
+                kind: EventKind::Ended,
+                session: Session {
+                    active: false,
+                    ..s.clone()
+                }
+            });
+
+        let started = newer.inner.iter()
+            .filter(|s| s.active && !was_active(s))
+            .map(|s| SessionEvent {
+                kind: EventKind::Started,
+                session: s.clone()
+            });
+
+        ended.chain(started).collect()
+    }
+}
+
+impl SessionEvent {
+    /// Encodes events into as many notification datagrams as needed to fit them all.
+    pub fn to_udp_payloads(events: Vec<SessionEvent>) -> EncodeDecodeResult<Vec<Vec<u8>>> {
+        let mut payloads = vec![];
+
+        for chunk in events.chunks(MAX_NOTIFY_EVENTS) {
+            let mut bytes: Vec<u8> = vec![];
+            bytes.extend(&WHERED_NOTIFY_MAGIC);
+
+            let entry_count = (chunk.len() as u16).to_be_bytes();
+            bytes.extend(&entry_count);
+
+            for event in chunk {
+                let entry = event.session.clone().to_udp_payload();
+
+                if entry.len() > MAX_ENTRY_LENGTH {
+                    return Err(EncodeDecodeError::InvalidEntryLength(entry.len()));
+                }
+
+                bytes.push(event.kind as u8);
+                bytes.extend(entry);
+            }
+
+            payloads.push(bytes);
+        }
+
+        Ok(payloads)
+    }
+
+    pub fn from_udp_payload(buffer: Payload, host: &str) -> WhereResult<Vec<Self>> {
+        let mut cursor = Cursor::new(&buffer[..]);
+        let mut events = vec![];
+
+        parse::read_field(&mut cursor, |buf| {
+            if buf != WHERED_NOTIFY_MAGIC {
+                Err(EncodeDecodeError::BadMagic(buf))?
+            } else {
+                Ok(())
+            }
+        })?;
+
+        let entry_count = parse::read_field(&mut cursor, |buf| Ok(u16::from_be_bytes(buf)))?;
+
+        for _ in 0..entry_count {
+            let kind = if parse::read_bool_field(&mut cursor)? {
+                EventKind::Started
+            } else {
+                EventKind::Ended
+            };
+            let session = Session::from_udp_payload(&mut cursor, host)?;
+
+            events.push(Self {
+                kind,
+                session
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+/// Encodes the reply to a subscription request.  A lease of 0 means the subscription was
+/// refused, and a cookie, when the client sent one, has to be sent back to subscribe or
+/// renew.  Refusing with a cookie asks the client to try again with it, which proves it
+/// receives datagrams at its address.
+pub fn subscription_ack(lease: u32, cookie: Option<u64>) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    bytes.extend(&WHERED_SUBSCRIBE_MAGIC);
+    bytes.extend(&lease.to_be_bytes());
+
+    if let Some(cookie) = cookie {
+        bytes.extend(&cookie.to_be_bytes());
+    }
+
+    bytes
+}
+
+/// Reads the lease and the cookie from the reply to a subscription request, the cookie
+/// being 0 if there is none.  Servers from before cookies send shorter replies.
+pub fn parse_subscription_ack(buf: &[u8]) -> EncodeDecodeResult<(u32, u64)> {
+    let (lease, cookie) = match buf.len() {
+        8 => (&buf[4..8], 0),
+        16 => (&buf[4..8], u64::from_be_bytes(buf[8..16].try_into().unwrap())),
+        _ => return Err(EncodeDecodeError::InvalidAckLength(buf.len()))
+    };
+
+    let magic: [u8; 4] = buf[..4].try_into().unwrap();
+
+    if magic != WHERED_SUBSCRIBE_MAGIC {
+        return Err(EncodeDecodeError::BadMagic(magic));
+    }
+
+    Ok((u32::from_be_bytes(lease.try_into().unwrap()), cookie))
+}